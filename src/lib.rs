@@ -1,24 +1,241 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
+use syn::parse::ParseStream;
 use syn::{
-    parse_macro_input, Data, DeriveInput, Fields, ImplItem, FnArg, ReturnType, ImplItemMethod, GenericArgument, PathArguments, Item, ItemEnum,
-    ItemImpl, ItemMod, token::Brace, ItemStruct, Type, Visibility, TypePath, AngleBracketedGenericArguments
+    parse_macro_input, Attribute, Data, DeriveInput, Fields, FnArg, GenericArgument, ImplItem,
+    Item, ItemImpl, ItemMod, ItemStruct, ItemTrait, Path, PathArguments, ReturnType, Token, TraitItem, Type,
+    TypeParamBound,
 };
 
+// Options of the `WithNew` derive, collected from `#[with_new(...)]` helper attributes, e.g.
+// `#[with_new(decode_error = crate::flyteidl::MessageDecodeError)]`.
+#[derive(Default)]
+struct WithNewOptions {
+    // Error type `prost::DecodeError` is converted into before it is raised in Python.
+    // It has to implement `From<prost::DecodeError>` and `Into<PyErr>`.
+    decode_error: Option<Path>,
+    // Error type `prost::EncodeError` is converted into before it is raised in Python.
+    // It has to implement `From<prost::EncodeError>` and `Into<PyErr>`.
+    encode_error: Option<Path>,
+    // Concrete instantiations of a generic struct exposed as Python classes, since PyO3 can't
+    // expose generic classes, e.g. `concrete(Foo<i64> = "FooInt", Foo<f64> = "FooFloat")`.
+    concrete: Vec<(Type, syn::LitStr)>,
+    // Keeps the GIL while encoding and decoding, which isn't worth releasing for tiny messages.
+    hold_gil: bool,
+    // Python module of the classes generated for `concrete` structs and data-carrying enums, which
+    // `pickle` imports them from, e.g. `module = "mypackage.protos"`.
+    module: Option<syn::LitStr>,
+    // Opt-in `__repr__()` in the form of a constructor call.
+    repr: bool,
+    // Opt-in `__str__()` returning the protobuf text format of messages.
+    str: bool,
+    // Opt-in `__eq__()` / `__ne__()` through `PartialEq`.
+    eq: bool,
+    // Opt-in `__hash__()` through `Hash`, for classes Python can't mutate once hashed: unit-only enums and
+    // the classes of data-carrying enums, which it makes `frozen`. Holds the option to point errors at it.
+    hash: Option<syn::Ident>,
+    // Unit enums deriving `prost::Enumeration`, named by `as_str_name()` / `from_str_name()` in Python.
+    // A derive can't see the other derives of its item, so this has to be set explicitly.
+    enumeration: bool,
+    // Type implementing the helpers parsing protobuf text format, e.g. the `ProtobufTextParser` of a
+    // `with_string` module, which sets it for the types of the module. Otherwise every type gets its own.
+    text_parser: Option<Path>,
+}
+
+impl WithNewOptions {
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("with_new")) {
+            attr.parse_args_with(|input: ParseStream| {
+                while !input.is_empty() {
+                    let key: syn::Ident = input.parse()?;
+                    match key.to_string().as_str() {
+                        "decode_error" => {
+                            input.parse::<Token![=]>()?;
+                            options.decode_error = Some(input.parse()?);
+                        }
+                        "encode_error" => {
+                            input.parse::<Token![=]>()?;
+                            options.encode_error = Some(input.parse()?);
+                        }
+                        "hold_gil" => options.hold_gil = true,
+                        "repr" => options.repr = true,
+                        "str" => options.str = true,
+                        "eq" => options.eq = true,
+                        "hash" => options.hash = Some(key.clone()),
+                        "enumeration" => options.enumeration = true,
+                        "text_parser" => {
+                            input.parse::<Token![=]>()?;
+                            options.text_parser = Some(input.parse()?);
+                        }
+                        "module" => {
+                            input.parse::<Token![=]>()?;
+                            options.module = Some(input.parse()?);
+                        }
+                        "concrete" => {
+                            let content;
+                            syn::parenthesized!(content in input);
+                            while !content.is_empty() {
+                                let ty: Type = content.parse()?;
+                                content.parse::<Token![=]>()?;
+                                options.concrete.push((ty, content.parse()?));
+                                if !content.is_empty() {
+                                    content.parse::<Token![,]>()?;
+                                }
+                            }
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                &key,
+                                format!("unknown `with_new` option `{}`", key),
+                            ))
+                        }
+                    }
+                    if !input.is_empty() {
+                        input.parse::<Token![,]>()?;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+
+    // Closure mapping `prost::DecodeError` into something `?` can raise as `PyErr`.
+    // Without a configured error type, it is raised as python `ValueError`.
+    fn decode_error_mapper(&self) -> proc_macro2::TokenStream {
+        match &self.decode_error {
+            Some(path) => quote! { <#path>::from },
+            None => quote! {
+                |err: prost::DecodeError| pyo3::exceptions::PyValueError::new_err(err.to_string())
+            },
+        }
+    }
+
+    // Type implementing the protobuf text format parsing helpers, and the helpers to generate into the type
+    // `name` itself when no `text_parser` is set.
+    fn text_parser(&self, name: &syn::Ident) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        match &self.text_parser {
+            Some(path) => (quote! { #path }, quote! {}),
+            None => (quote! { #name }, text_parse_helpers()),
+        }
+    }
+
+    // Runs `work`, the prost encoding or decoding of a message, with the GIL released unless `hold_gil` is set.
+    fn release_gil(&self) -> proc_macro2::TokenStream {
+        if self.hold_gil {
+            quote! { work() }
+        } else {
+            quote! { py.allow_threads(work) }
+        }
+    }
+
+    // Decodes the bytes `bt` of the buffer `buffer` with `decode`, in place. Mutable buffers like `bytearray`
+    // are decoded holding the GIL, since other threads could write to them once it's released.
+    fn decode_buffer(&self) -> proc_macro2::TokenStream {
+        if self.hold_gil {
+            quote! { Self::release_gil(py, || decode(bt)) }
+        } else {
+            quote! {
+                if buffer.readonly() {
+                    Self::release_gil(py, || decode(bt))
+                } else {
+                    decode(bt)
+                }
+            }
+        }
+    }
+
+    // Opt-in `__eq__()`, `__ne__()` and `__hash__()` comparing and hashing `self`, or its field `field`
+    // like `.0` for wrapper classes. Instances of other classes compare as `NotImplemented`.
+    fn comparison_methods(&self, field: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let eq = if self.eq {
+            quote! {
+                pub fn __eq__(&self, other: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyObject {
+                    use pyo3::prelude::*;
+                    let py = other.py();
+                    match other.downcast::<Self>() {
+                        Ok(other) => pyo3::IntoPy::into_py(PartialEq::eq(&(*self) #field, &(*other.borrow()) #field), py),
+                        Err(_) => py.NotImplemented(),
+                    }
+                }
+
+                pub fn __ne__(&self, other: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyObject {
+                    use pyo3::prelude::*;
+                    let py = other.py();
+                    match other.downcast::<Self>() {
+                        Ok(other) => pyo3::IntoPy::into_py(PartialEq::ne(&(*self) #field, &(*other.borrow()) #field), py),
+                        Err(_) => py.NotImplemented(),
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+        let hash = if self.hash.is_some() {
+            quote! {
+                pub fn __hash__(&self) -> u64 {
+                    use ::std::hash::{Hash, Hasher};
+                    let mut hasher = ::std::collections::hash_map::DefaultHasher::new();
+                    (*self) #field.hash(&mut hasher);
+                    hasher.finish()
+                }
+            }
+        } else {
+            quote! {}
+        };
+        quote! { #eq #hash }
+    }
+
+    // `module = "..."` argument of the `#[pyclass]` attributes of generated classes.
+    fn pyclass_module(&self) -> proc_macro2::TokenStream {
+        match &self.module {
+            Some(module) => quote! { , module = #module },
+            None => quote! {},
+        }
+    }
 
-#[proc_macro_derive(WithNew)]
+    // Closure mapping `prost::EncodeError` into something `?` can raise as `PyErr`.
+    // Without a configured error type, it is raised as python `ValueError`.
+    fn encode_error_mapper(&self) -> proc_macro2::TokenStream {
+        match &self.encode_error {
+            Some(path) => quote! { <#path>::from },
+            None => quote! {
+                |err: prost::EncodeError| pyo3::exceptions::PyValueError::new_err(err.to_string())
+            },
+        }
+    }
+}
+
+#[proc_macro_derive(WithNew, attributes(with_new))]
 pub fn with_new(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
+    let options = match WithNewOptions::from_attrs(&input.attrs) {
+        Ok(options) => options,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    // Messages and `concrete` classes have setters, so their hash could change while they are in a set
+    if let (Some(hash), Data::Struct(_)) = (&options.hash, &input.data) {
+        return syn::Error::new_spanned(hash, "`hash` is only supported on enums, the classes of structs are mutable")
+            .to_compile_error()
+            .into();
+    }
+    let decode_error = options.decode_error_mapper();
+    let encode_error = options.encode_error_mapper();
+    let release_gil = options.release_gil();
+    let decode_buffer = options.decode_buffer();
+    let module = options.pyclass_module();
     let name = &input.ident;
     let generics = &input.generics;
     let generic_params: Vec<_> = generics.params.iter().collect();
     // `where_clause` for future traits bounds handling
-    let where_clause = &generics.where_clause;
+    let _where_clause = &generics.where_clause;
 
     let gen = match &input.data {
         Data::Struct(data) => {
+            use syn::ext::IdentExt;
             // Extract the list of field names and types
             let (field_names, field_types): (Vec<_>, Vec<_>) = match &data.fields {
                 Fields::Named(ref fields_named) => fields_named
@@ -70,7 +287,106 @@ pub fn with_new(input: TokenStream) -> TokenStream {
                     quote! { #(#optional_field_names = None),* }
                 };
 
+            // `oneof` fields, prost generates them as `Option`s of `Oneof` enums
+            let (oneof_names, oneof_types): (Vec<_>, Vec<_>) = match &data.fields {
+                Fields::Named(fields_named) => fields_named
+                    .named
+                    .iter()
+                    .filter(|field| prost_keys(&field.attrs).iter().any(|key| key == "oneof"))
+                    .filter_map(|field| Some((field.ident.clone(), optional_type(&field.ty)?)))
+                    .unzip(),
+                _ => (Vec::new(), Vec::new()),
+            };
+
+            // Variants of `oneof` fields are passed as keyword arguments named after them, like python protobuf
+            let constructor = if oneof_names.is_empty() {
+                quote! {
+                    #[new]
+                    #[pyo3(signature = ( #combined_signatures ) )]
+                    #[allow(clippy::too_many_arguments)]
+                    pub fn new(#combined_arguments) -> Self {
+                        Self {
+                            #all_values
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    #[new]
+                    #[pyo3(signature = ( #combined_signatures, **oneof_fields ) )]
+                    #[allow(clippy::too_many_arguments)]
+                    pub fn new(
+                        #combined_arguments,
+                        oneof_fields: Option<&pyo3::Bound<'_, pyo3::types::PyDict>>,
+                    ) -> pyo3::PyResult<Self> {
+                        use pyo3::prelude::*;
+                        let mut message = Self {
+                            #all_values
+                        };
+                        for (field, value) in oneof_fields.into_iter().flatten() {
+                            let field: String = field.extract()?;
+                            if !message.set_oneof_field(&field, &value)? {
+                                return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                                    "{}() got an unexpected keyword argument '{}'",
+                                    stringify!(#name),
+                                    field
+                                )));
+                            }
+                        }
+                        Ok(message)
+                    }
+                }
+            };
+            let (oneof_impl, oneof_methods) = oneof_field_methods(name, &oneof_names, &oneof_types);
+            let presence_methods = field_presence_methods(name, &field_names, &field_types, &oneof_names, &oneof_types);
+            let merge_from = merge_from_method(name, &data.fields);
+            let (json_impl, json_methods) = json_methods(name, &data.fields);
+            let (text_impl, text_methods) = text_methods(name, &data.fields, &options);
+            let repr = if options.repr {
+                // `oneof` fields are represented by their set variant
+                let fields: Vec<_> = required_field_names
+                    .iter()
+                    .chain(optional_field_names.iter())
+                    .map(|name| **name)
+                    .filter(|name| !oneof_names.contains(*name))
+                    .collect();
+                repr_method(&fields, &oneof_names, quote! {})
+            } else {
+                quote! {}
+            };
+            let str = if options.str {
+                quote! {
+                    // Protobuf text format of the message, like `str()` of python protos.
+                    pub fn __str__(&self) -> String {
+                        let mut out = String::new();
+                        self.write_text(&mut out, 0);
+                        out
+                    }
+                }
+            } else {
+                quote! {}
+            };
+            let comparisons = options.comparison_methods(quote! {});
+            // Python names and types of the constructor arguments, for the type stub of the class
+            let stub_arguments = |fields: &[(&Option<syn::Ident>, &&Type)], substitutions: &[(String, proc_macro2::TokenStream)]| {
+                fields
+                    .iter()
+                    .map(|(name, ty)| {
+                        let ty = syn::parse2(substitute_generics(quote! { #ty }, substitutions)).unwrap_or_else(|_| (**ty).clone());
+                        (name.as_ref().unwrap().unraw().to_string(), py_type(&ty))
+                    })
+                    .collect::<Vec<_>>()
+            };
+
             if generic_params.is_empty() {
+                let oneof_arguments = if oneof_names.is_empty() { "" } else { "**oneof_fields: typing.Any" };
+                let stub = message_stub(
+                    &name.to_string(),
+                    &data.fields,
+                    constructor_stub(&stub_arguments(&required_fields, &[]), &stub_arguments(&optional_fields, &[]), oneof_arguments),
+                    !oneof_names.is_empty(),
+                    &options,
+                );
                 // Implement methods template of the `new()` function
                 quote! {                    
                     #[pymethods]
@@ -82,419 +398,4898 @@ pub fn with_new(input: TokenStream) -> TokenStream {
                         // Most arguments are required by default, except for trailing Option<_> arguments, which are implicitly given a default of None.
                         // This behaviour can be configured by the #[pyo3(signature = (...))] option which allows writing a signature in Python syntax.
                         // https://pyo3.rs/v0.21.2/function/signature#trailing-optional-arguments
-                        #[new]
-                        #[pyo3(signature = ( #combined_signatures ) )]
-                        pub fn new(#combined_arguments) -> Self {
-                            Self {
-                                #all_values
+                        #constructor
+
+                        #oneof_methods
+
+                        #presence_methods
+
+                        // Replaces all fields by the ones of `other`, like python protobuf.
+                        pub fn CopyFrom(slf: &pyo3::Bound<'_, Self>, other: &pyo3::Bound<'_, Self>) {
+                            let other = other.borrow().clone();
+                            *slf.borrow_mut() = other;
+                        }
+
+                        // Merges `other` into this message, like python protobuf.
+                        pub fn MergeFrom(slf: &pyo3::Bound<'_, Self>, other: &pyo3::Bound<'_, Self>) {
+                            // `other` may be this message itself, so it is copied before borrowing mutably
+                            let other = other.borrow().clone();
+                            slf.borrow_mut().merge_from(&other);
+                        }
+
+                        // Mirrors `ParseFromString()` of python protos, replaces the message by the decoded one in place
+                        // and returns the number of bytes read. The message is left unchanged if decoding fails.
+                        // Like all decoding methods, it accepts any buffer protocol object and decodes without copying.
+                        pub fn ParseFromString(&mut self, bytes_string: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<usize> {
+                            let (message, len) = Self::with_buffer(bytes_string, |bt| Ok((prost::Message::decode(bt)?, bt.len())))?;
+                            *self = message;
+                            Ok(len)
+                        }
+
+                        // Mirrors `MergeFromString()` of python protos, merges the decoded message into this one
+                        // and returns the number of bytes read.
+                        pub fn MergeFromString(&mut self, bytes_string: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<usize> {
+                            Self::with_buffer(bytes_string, |bt| {
+                                prost::Message::merge(self, bt)?;
+                                Ok(bt.len())
+                            })
+                        }
+
+                        // Mirrors `FromString()` of python protos, returns the decoded message.
+                        #[staticmethod]
+                        pub fn FromString(bytes_string: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<Self> {
+                            Self::with_buffer(bytes_string, |bt| prost::Message::decode(bt))
+                        }
+
+                        // Replaces the message by the length-delimited one at the start of `bytes_string` in place,
+                        // and returns the number of bytes read, so that records can be read one after the other.
+                        pub fn ParseDelimited(&mut self, bytes_string: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<usize> {
+                            let (message, len) = Self::with_buffer(bytes_string, |bt| {
+                                let mut buf = bt;
+                                let message = prost::Message::decode_length_delimited(&mut buf)?;
+                                Ok((message, bt.len() - buf.len()))
+                            })?;
+                            *self = message;
+                            Ok(len)
+                        }
+
+                        // Returns the encoded message prefixed with its length as varint, like records of
+                        // `iter_delimited()` or `writeDelimitedTo()` of java protos.
+                        pub fn SerializeDelimited(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<pyo3::PyObject> {
+                            let buf = Self::release_gil(py, || {
+                                let len = prost::Message::encoded_len(self);
+                                let mut buf = Vec::with_capacity(prost::length_delimiter_len(len) + len);
+                                prost::Message::encode_length_delimited(self, &mut buf).map(|_| buf)
+                            });
+                            Ok(pyo3::types::PyBytes::new_bound(py, &buf.map_err(#encode_error)?).into())
+                        }
+
+                        #json_methods
+
+                        #text_methods
+
+                        #repr
+
+                        #str
+
+                        #comparisons
+
+                        // Size of the encoded message in bytes.
+                        pub fn ByteSize(&self) -> usize {
+                            prost::Message::encoded_len(self)
+                        }
+
+                        // Mirrors `SerializeToString()` of python protos, returns the encoded message as `bytes`.
+                        pub fn SerializeToString(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<pyo3::PyObject> {
+                            let buf = Self::release_gil(py, || {
+                                let mut buf = Vec::with_capacity(prost::Message::encoded_len(self));
+                                prost::Message::encode(self, &mut buf).map(|_| buf)
+                            });
+                            Ok(pyo3::types::PyBytes::new_bound(py, &buf.map_err(#encode_error)?).into())
+                        }
+
+                        // Pickles the message as its encoding, which `__setstate__()` decodes into
+                        // `type(self)(...)` called with the default values of the required arguments.
+                        pub fn __reduce__(slf: &pyo3::Bound<'_, Self>) -> pyo3::PyResult<(pyo3::PyObject, pyo3::PyObject, pyo3::PyObject)> {
+                            use pyo3::prelude::*;
+                            let py = slf.py();
+                            Ok((
+                                slf.get_type().into_any().unbind(),
+                                Self::py_default_args(py).into_any().unbind(),
+                                slf.borrow().__getstate__(py)?,
+                            ))
+                        }
+
+                        pub fn __getstate__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<pyo3::PyObject> {
+                            self.SerializeToString(py)
+                        }
+
+                        pub fn __setstate__(&mut self, state: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<()> {
+                            self.ParseFromString(state).map(|_| ())
+                        }
+
+                        pub fn __copy__(slf: &pyo3::Bound<'_, Self>) -> pyo3::PyResult<pyo3::PyObject> {
+                            Self::py_copy(slf)
+                        }
+
+                        // Messages own all their data, so a copy is always deep.
+                        pub fn __deepcopy__(slf: &pyo3::Bound<'_, Self>, _memo: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<pyo3::PyObject> {
+                            Self::py_copy(slf)
+                        }
+                    }
+
+                    impl #name {
+                        // Type stub of the class, see `py_stubs()` of `with_string` modules.
+                        pub const PY_STUB: &'static str = #stub;
+
+                        // Arguments of the constructor creating a default message, the defaults of the required fields.
+                        fn py_default_args(py: pyo3::Python<'_>) -> pyo3::Bound<'_, pyo3::types::PyTuple> {
+                            let args: Vec<pyo3::PyObject> = vec![
+                                #(pyo3::IntoPy::into_py(<#required_field_types as ::std::default::Default>::default(), py)),*
+                            ];
+                            pyo3::types::PyTuple::new_bound(py, args)
+                        }
+
+                        // Copy of the message with the class of `slf`, which may be a Python subclass.
+                        fn py_copy(slf: &pyo3::Bound<'_, Self>) -> pyo3::PyResult<pyo3::PyObject> {
+                            use pyo3::prelude::*;
+                            let copy = slf.get_type().call1(Self::py_default_args(slf.py()))?;
+                            *copy.downcast::<Self>()?.borrow_mut() = slf.borrow().clone();
+                            Ok(copy.unbind())
+                        }
+
+                        // Decodes the bytes of a buffer protocol object, e.g. `bytes`, `bytearray`, `memoryview`
+                        // or `mmap`, with `decode`, borrowing them instead of copying when possible.
+                        fn with_buffer<R: Send>(
+                            bytes_string: &pyo3::Bound<'_, pyo3::PyAny>,
+                            decode: impl Send + FnOnce(&[u8]) -> Result<R, prost::DecodeError>,
+                        ) -> pyo3::PyResult<R> {
+                            let py = bytes_string.py();
+                            let buffer = pyo3::buffer::PyBuffer::<u8>::get_bound(bytes_string)?;
+                            if !buffer.is_c_contiguous() {
+                                return Err(pyo3::exceptions::PyBufferError::new_err("buffer is not contiguous"));
                             }
+                            let bt: &[u8] = if buffer.len_bytes() == 0 {
+                                &[]
+                            } else {
+                                // Safety: the buffer is contiguous, and its memory stays valid until `buffer` is released
+                                unsafe { ::std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, buffer.len_bytes()) }
+                            };
+                            let decoded = #decode_buffer;
+                            decoded.map_err(#decode_error).map_err(Into::into)
                         }
 
-                        // use prost::Message;
-                        // use pyo3::types::PyBytes;
-                        pub fn ParseFromString(&mut self, bytes_string: &pyo3::types::PyBytes) -> Result<#name, crate::flyteidl::MessageDecodeError> {
-                            let bt = bytes_string.as_bytes();
-                            let de = prost::Message::decode(&bt.to_vec()[..]);
-                            Ok(de?)
+                        #[allow(unused_variables)]
+                        fn release_gil<R: Send>(py: pyo3::Python<'_>, work: impl Send + FnOnce() -> R) -> R {
+                            #release_gil
                         }
-                        // TODO: 
-                        // pub fn SerializeToString(proto_obj: #name) -> Result<Vec<u8>, crate::flyteidl::MessageEncodeError> {
-                        //     let mut buf = vec![];
-                        //     proto_obj.encode(&mut buf)?;
-                        //     Ok(buf)
-                        // }
                     }
 
+                    #oneof_impl
+
+                    #merge_from
+
+                    #json_impl
+
+                    #text_impl
 
                     // https://github.com/hyperium/tonic/blob/c7836521dd417434d625bd653fcf00fb7f7ae25e/tonic/src/request.rs#L28
                 }
-            } else {
+            } else if options.concrete.is_empty() {
                 // https://pyo3.rs/v0.21.2/class#no-generic-parameters
-                // TODO: Implement methods template of the `new()` function for generic structs
-                quote! {}
+                syn::Error::new_spanned(
+                    generics,
+                    format!(
+                        "generic structs need `#[with_new(concrete({}<...> = \"PythonName\"))]` to be exposed to Python",
+                        name
+                    ),
+                )
+                .to_compile_error()
+            } else {
+                // PyO3 can't expose generic classes, so each concrete instantiation is wrapped in its own class
+                let concrete_classes = options.concrete.iter().map(|(concrete, python_name)| {
+                    let generic_args = match concrete_arguments(name, concrete) {
+                        Ok(generic_args) => generic_args,
+                        Err(err) => return err.to_compile_error(),
+                    };
+                    let params: Vec<_> = generic_params
+                        .iter()
+                        .filter_map(|param| match param {
+                            syn::GenericParam::Type(param) => Some(param.ident.to_string()),
+                            syn::GenericParam::Const(param) => Some(param.ident.to_string()),
+                            syn::GenericParam::Lifetime(_) => None,
+                        })
+                        .collect();
+                    if params.len() != generic_args.len() {
+                        return syn::Error::new_spanned(
+                            concrete,
+                            format!("expected {} generic arguments for `{}`", params.len(), name),
+                        )
+                        .to_compile_error();
+                    }
+                    let substitutions: Vec<_> = params.into_iter().zip(generic_args).collect();
+                    let arguments = substitute_generics(combined_arguments.clone(), &substitutions);
+                    let concrete_types: Vec<_> = field_types
+                        .iter()
+                        .map(|ty| substitute_generics(quote! { #ty }, &substitutions))
+                        .collect();
+                    let getters: Vec<_> = field_names.iter().map(|field| format_ident!("get_{}", field.as_ref().unwrap())).collect();
+                    let setters: Vec<_> = field_names.iter().map(|field| format_ident!("set_{}", field.as_ref().unwrap())).collect();
+                    let class = format_ident!("Py{}", python_name.value());
+                    // Pickled field-wise, as the arguments of the constructor
+                    let state_fields: Vec<_> = required_field_names.iter().chain(optional_field_names.iter()).collect();
+                    let state_types: Vec<_> = required_field_types
+                        .iter()
+                        .chain(optional_field_types.iter())
+                        .map(|ty| substitute_generics(quote! { #ty }, &substitutions))
+                        .collect();
+                    let state_indices = 0..state_fields.len();
+                    let repr = if options.repr {
+                        repr_method(&state_fields.iter().map(|name| ***name).collect::<Vec<_>>(), &[], quote! { .0 })
+                    } else {
+                        quote! {}
+                    };
+                    let comparisons = options.comparison_methods(quote! { .0 });
+                    let stub = concrete_stub(
+                        &python_name.value(),
+                        &field_names.iter().map(|field| field.as_ref().unwrap().unraw().to_string()).collect::<Vec<_>>(),
+                        &concrete_types
+                            .iter()
+                            .map(|ty| syn::parse2(ty.clone()).map(|ty| py_type(&ty)).unwrap_or_else(|_| "typing.Any".to_string()))
+                            .collect::<Vec<_>>(),
+                        constructor_stub(
+                            &stub_arguments(&required_fields, &substitutions),
+                            &stub_arguments(&optional_fields, &substitutions),
+                            "",
+                        ),
+                        &options,
+                    );
+
+                    quote! {
+                        #[pyo3::pyclass(name = #python_name #module)]
+                        pub struct #class(pub #concrete);
+
+                        impl #class {
+                            // Type stub of the class, see `py_stubs()` of `with_string` modules.
+                            pub const PY_STUB: &'static str = #stub;
+                        }
+
+                        #[pyo3::pymethods]
+                        impl #class {
+                            #[new]
+                            #[pyo3(signature = ( #combined_signatures ) )]
+                            #[allow(clippy::too_many_arguments)]
+                            pub fn new(#arguments) -> Self {
+                                Self(#name {
+                                    #all_values
+                                })
+                            }
+
+                            #(
+                                #[getter]
+                                fn #getters(&self) -> #concrete_types {
+                                    self.0.#field_names.clone()
+                                }
+
+                                #[setter]
+                                fn #setters(&mut self, value: #concrete_types) {
+                                    self.0.#field_names = value;
+                                }
+                            )*
+
+                            pub fn __reduce__(slf: &pyo3::Bound<'_, Self>) -> (pyo3::PyObject, pyo3::PyObject) {
+                                use pyo3::prelude::*;
+                                (slf.get_type().into_any().unbind(), slf.borrow().__getstate__(slf.py()))
+                            }
+
+                            pub fn __getstate__(&self, py: pyo3::Python<'_>) -> pyo3::PyObject {
+                                let state: Vec<pyo3::PyObject> = vec![
+                                    #(pyo3::IntoPy::into_py(self.0.#state_fields.clone(), py)),*
+                                ];
+                                pyo3::types::PyTuple::new_bound(py, state).into()
+                            }
+
+                            #[allow(unused_variables)]
+                            pub fn __setstate__(&mut self, state: &pyo3::Bound<'_, pyo3::types::PyTuple>) -> pyo3::PyResult<()> {
+                                use pyo3::prelude::*;
+                                self.0 = #name {
+                                    #(#state_fields: state.get_item(#state_indices)?.extract::<#state_types>()?),*
+                                };
+                                Ok(())
+                            }
+
+                            pub fn __copy__(&self) -> Self {
+                                Self(#name {
+                                    #(#field_names: self.0.#field_names.clone()),*
+                                })
+                            }
+
+                            pub fn __deepcopy__(&self, _memo: &pyo3::Bound<'_, pyo3::PyAny>) -> Self {
+                                self.__copy__()
+                            }
+
+                            #repr
+
+                            #comparisons
+                        }
+
+                        impl ::std::convert::From<#concrete> for #class {
+                            fn from(other: #concrete) -> Self {
+                                Self(other)
+                            }
+                        }
+
+                        impl ::std::convert::From<#class> for #concrete {
+                            fn from(other: #class) -> Self {
+                                other.0
+                            }
+                        }
+                    }
+                });
+                quote! { #(#concrete_classes)* }
             }
         }
-        // Data::Struct(data) => {
-        //     // Extract the list of field names and types
-        //     let (field_names, field_types): (Vec<_>, Vec<_>) = match &data.fields {
-        //         Fields::Named(ref fields_named) => fields_named
-        //             .named
-        //             .iter()
-        //             .map(|f| (f.ident.clone(), &f.ty))
-        //             .unzip(),
-        //         _ => panic!("AutoPyClass can only be used with structs with named fields"),
-        //     };
-        
-        //     let all_values = quote! { #(#field_names),*};
-        
-        //     // Make all fields optional
-        //     let optional_field_names: Vec<_> = field_names.iter().collect();
-        //     let optional_field_types: Vec<_> = field_types.iter().map(|ty| quote! { #ty }).collect();
-        
-        //     let optional_part = if !optional_field_names.is_empty() {
-        //         quote! { #(#optional_field_names: #optional_field_types),* }
-        //     } else {
-        //         quote! {}
-        //     };
-        
-        //     let combined_signatures = if !optional_field_names.is_empty() {
-        //         quote! { #(#optional_field_names = None),* }
-        //     } else {
-        //         quote! {}
-        //     };
-        
-        //     if generic_params.is_empty() {
-        //         // Implement methods template of the `new()` function
-        //         quote! {
-        //             #[::pyo3::pymethods]
-        //             impl #name {
-        //                 #[new]
-        //                 #[pyo3(signature = ( #combined_signatures ) )]
-        //                 pub fn new(#optional_part) -> Self {
-        //                     Self {
-        //                         #all_values
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //     } else {
-        //         // TODO: Implement methods template of the `new()` function for generic structs
-        //         quote! {}
-        //     }
-        // }
-        Data::Enum(_data) => {
-            quote! {}
+        Data::Enum(data) => {
+            if data.variants.iter().all(|variant| matches!(variant.fields, Fields::Unit)) {
+                unit_enum_methods(&input, data, &options)
+            } else {
+                data_enum_class(name, data, &options)
+            }
         }
         Data::Union(_data) => {
             quote! {}
         }
-        _ => panic!("AutoPyClass can only be used with structs enums and unions"),
     };
 
     TokenStream::from(gen)
 }
 
-#[proc_macro_attribute]
-pub fn with_pyclass(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as Item);
+// `WhichOneof()` and the fields of the variants of `oneof` fields, which are looked up through
+// `__getattr__` / `__setattr__` since the variants are only known to the derive of the `Oneof` enum.
+// Setting a variant clears its siblings, unset variants read as their default value.
+// Returns the helper impl and the methods going into the `#[pymethods]` impl of the message.
+fn oneof_field_methods(
+    name: &syn::Ident,
+    oneof_names: &[Option<syn::Ident>],
+    oneof_types: &[&Type],
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    if oneof_names.is_empty() {
+        return (quote! {}, quote! {});
+    }
+    let oneof_groups: Vec<_> = oneof_names.iter().map(|field| field.as_ref().unwrap().to_string()).collect();
 
-    // Reconstruct the struct or enum definition block
-    let output = match input {
-        Item::Struct(item_struct) => {
-            quote! {
+    let oneof_impl = quote! {
+        impl #name {
+            // Sets the variant `field` of a `oneof`, `None` clears it. Returns whether `field` is such a variant.
+            fn set_oneof_field(&mut self, field: &str, value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<bool> {
+                #(
+                    if <#oneof_types>::PY_ONEOF_FIELDS.contains(&field) {
+                        if value.is_none() {
+                            if self.#oneof_names.as_ref().is_some_and(|variant| variant.py_oneof_field() == field) {
+                                self.#oneof_names = None;
+                            }
+                        } else {
+                            self.#oneof_names = Some(<#oneof_types>::py_oneof_from(field, value)?);
+                        }
+                        return Ok(true);
+                    }
+                )*
+                Ok(false)
+            }
+
+            // Sets the attribute `field`, or deletes it for a `value` of `None`, like `object.__setattr__()`:
+            // through the data descriptor of the class, e.g. the setter of a `set_all` field, or else in the
+            // instance `__dict__`. CPython refuses to call `object.__setattr__()` for classes overriding it in C.
+            fn py_generic_setattr(
+                slf: &pyo3::Bound<'_, Self>,
+                field: &pyo3::Bound<'_, pyo3::types::PyString>,
+                value: Option<&pyo3::Bound<'_, pyo3::PyAny>>,
+            ) -> pyo3::PyResult<()> {
                 use pyo3::prelude::*;
-                #[pyclass(subclass, dict, get_all, set_all)]
-                #item_struct
+                let mut class_attribute = None;
+                for class in slf.get_type().getattr("__mro__")?.iter()? {
+                    if let Ok(attribute) = class?.getattr("__dict__")?.get_item(field) {
+                        class_attribute = Some(attribute);
+                        break;
+                    }
+                }
+                if let Some(descriptor) = &class_attribute {
+                    if descriptor.hasattr("__set__")? {
+                        match value {
+                            Some(value) => descriptor.call_method1("__set__", (slf, value))?,
+                            None => descriptor.call_method1("__delete__", (slf,))?,
+                        };
+                        return Ok(());
+                    }
+                }
+                let missing = || {
+                    pyo3::exceptions::PyAttributeError::new_err(match class_attribute {
+                        Some(_) => format!("'{}' object attribute '{}' is read-only", stringify!(#name), field),
+                        None => format!("'{}' object has no attribute '{}'", stringify!(#name), field),
+                    })
+                };
+                let dict = match slf.getattr("__dict__") {
+                    Ok(dict) => dict.downcast_into::<pyo3::types::PyDict>()?,
+                    Err(_) => return Err(missing()),
+                };
+                match value {
+                    Some(value) => dict.set_item(field, value),
+                    None => dict.del_item(field).map_err(|_| missing()),
+                }
             }
         }
-        Item::Enum(item_enum) => {
+    };
+
+    let oneof_methods = quote! {
+        // Field name of the variant set in the `oneof` group, like python protobuf.
+        pub fn WhichOneof(&self, oneof_group: &str) -> pyo3::PyResult<Option<&'static str>> {
+            match oneof_group {
+                #(#oneof_groups => Ok(self.#oneof_names.as_ref().map(|variant| variant.py_oneof_field())),)*
+                _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Protocol message has no oneof {:?} field.",
+                    oneof_group
+                ))),
+            }
+        }
+
+        // Only called when there is no such attribute, so fields and methods are looked up as usual
+        fn __getattr__(&self, py: pyo3::Python<'_>, field: &str) -> pyo3::PyResult<pyo3::PyObject> {
+            #(
+                if <#oneof_types>::PY_ONEOF_FIELDS.contains(&field) {
+                    return Ok(match &self.#oneof_names {
+                        Some(variant) if variant.py_oneof_field() == field => variant.py_oneof_value(py),
+                        _ => <#oneof_types>::py_oneof_default(py, field),
+                    });
+                }
+            )*
+            Err(pyo3::exceptions::PyAttributeError::new_err(format!(
+                "'{}' object has no attribute '{}'",
+                stringify!(#name),
+                field
+            )))
+        }
+
+        fn __setattr__(
+            slf: &pyo3::Bound<'_, Self>,
+            field: &pyo3::Bound<'_, pyo3::types::PyString>,
+            value: &pyo3::Bound<'_, pyo3::PyAny>,
+        ) -> pyo3::PyResult<()> {
+            use pyo3::prelude::*;
+            if slf.borrow_mut().set_oneof_field(&field.to_cow()?, value)? {
+                return Ok(());
+            }
+            // Everything else, e.g. the fields of `set_all`, is set as usual
+            Self::py_generic_setattr(slf, field, Some(value))
+        }
+
+        fn __delattr__(slf: &pyo3::Bound<'_, Self>, field: &pyo3::Bound<'_, pyo3::types::PyString>) -> pyo3::PyResult<()> {
+            use pyo3::prelude::*;
+            if slf.borrow_mut().set_oneof_field(&field.to_cow()?, &slf.py().None().into_bound(slf.py()))? {
+                return Ok(());
+            }
+            Self::py_generic_setattr(slf, field, None)
+        }
+    };
+
+    (oneof_impl, oneof_methods)
+}
+
+// `merge_from()` merging another message following protobuf rules: set scalars overwrite, repeated fields
+// append, maps merge by key and sub-messages merge recursively. Sub-messages merge their encoding through
+// prost, which follows the same rules, so well-known types like `prost_types::Timestamp` merge too.
+fn merge_from_method(name: &syn::Ident, fields: &Fields) -> proc_macro2::TokenStream {
+    let merges = fields.iter().map(|field| {
+        let field_name = &field.ident;
+        let keys = prost_keys(&field.attrs);
+        if keys.iter().any(|key| key == "repeated" || PROST_MAPS.contains(&key.as_str())) {
+            quote! { self.#field_name.extend(other.#field_name.clone()); }
+        } else if keys.iter().any(|key| key == "oneof" || key == "message") {
+            // `oneof`s merge the same variant and replace other ones
+            let merge = if keys.iter().any(|key| key == "oneof") {
+                quote! { value.merge_from(other) }
+            } else {
+                merge_message(quote! { value }, quote! { other })
+            };
             quote! {
-                use pyo3::prelude::*;
-                #[pyclass(get_all, set_all)]
-                #item_enum
+                match (&mut self.#field_name, &other.#field_name) {
+                    (Some(value), Some(other)) => #merge,
+                    (value, Some(other)) => *value = Some(other.clone()),
+                    _ => {}
+                }
+            }
+        } else if keys.iter().any(|key| key == "optional") {
+            quote! {
+                if other.#field_name.is_some() {
+                    self.#field_name = other.#field_name.clone();
+                }
+            }
+        } else {
+            let ty = &field.ty;
+            quote! {
+                if other.#field_name != <#ty as ::std::default::Default>::default() {
+                    self.#field_name = other.#field_name.clone();
+                }
             }
         }
-        _ => {
-            return syn::Error::new_spanned(
-                input,
-                "with_pyclass can only be used with structs or enums",
-            )
-            .to_compile_error()
-            .into();
+    });
+
+    quote! {
+        impl #name {
+            pub fn merge_from(&mut self, other: &Self) {
+                #(#merges)*
+            }
+        }
+    }
+}
+
+// Merges the message `other` into the message `value`, both `prost::Message`s, through the encoding of `other`.
+// Decoding an encoding prost just made doesn't fail.
+fn merge_message(value: proc_macro2::TokenStream, other: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
+        ::prost::Message::merge(#value, ::prost::Message::encode_to_vec(#other).as_slice())
+            .expect("a message merges its own encoding")
+    }
+}
+
+// Keys of the `#[prost(...)]` attribute of a field, e.g. `["message", "optional", "tag"]`.
+fn prost_keys(attrs: &[Attribute]) -> Vec<String> {
+    prost_attrs(attrs).into_iter().map(|(key, _)| key).collect()
+}
+
+// Keys and string values of the `#[prost(...)]` attribute of a field, e.g. `("enumeration", Some("Mood"))`.
+fn prost_attrs(attrs: &[Attribute]) -> Vec<(String, Option<String>)> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("prost"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|nested| match nested {
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) => path.get_ident().map(|ident| (ident.to_string(), None)),
+            syn::NestedMeta::Meta(syn::Meta::NameValue(name_value)) => {
+                let value = match &name_value.lit {
+                    syn::Lit::Str(value) => Some(value.value()),
+                    _ => None,
+                };
+                name_value.path.get_ident().map(|ident| (ident.to_string(), value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Protobuf type of a field or `oneof` variant, from its `#[prost(...)]` attribute.
+enum ProtoType {
+    // Scalar types like `int64`, `string` or `bytes`
+    Scalar(String),
+    // `enumeration = "Mood"`, stored as `i32`
+    Enumeration(Path),
+    Message,
+}
+
+// Keys of map fields in `#[prost(...)]` attributes.
+const PROST_MAPS: [&str; 3] = ["map", "hash_map", "btree_map"];
+
+const PROTO_SCALARS: [&str; 15] = [
+    "double", "float", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32", "fixed64", "sfixed32",
+    "sfixed64", "bool", "string", "bytes",
+];
+
+impl ProtoType {
+    fn from_attrs(attrs: &[Attribute]) -> Option<Self> {
+        prost_attrs(attrs).into_iter().find_map(|(key, value)| match (key.as_str(), value) {
+            ("message", _) => Some(Self::Message),
+            ("enumeration", Some(path)) => syn::parse_str(&path).ok().map(Self::Enumeration),
+            (scalar, _) if PROTO_SCALARS.contains(&scalar) => Some(Self::Scalar(key)),
+            _ => None,
+        })
+    }
+
+    // Value type of `map = "string, enumeration(Mood)"`.
+    fn from_map_value(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value == "message" {
+            Some(Self::Message)
+        } else if let Some(path) = value.strip_prefix("enumeration(").and_then(|path| path.strip_suffix(')')) {
+            syn::parse_str(path).ok().map(Self::Enumeration)
+        } else {
+            PROTO_SCALARS.contains(&value).then(|| Self::Scalar(value.to_string()))
         }
+    }
+
+    // Expression converting `value`, a reference to a value of this type, into its proto3 JSON
+    // representation as Python object, e.g. `int64` as `str`. `ty` is the Rust type of messages, which
+    // pass on the options `preserving_proto_field_name` and `including_default_value_fields` in scope.
+    fn json_of(&self, ty: &Type, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let json = match self {
+            Self::Scalar(scalar) => scalar_to_json(scalar, value),
+            Self::Enumeration(path) => quote! {
+                match <#path>::try_from(*#value) {
+                    Ok(variant) => Ok(variant.as_str_name().into_py(py)),
+                    Err(_) => Ok((*#value).into_py(py)),
+                }
+            },
+            Self::Message => match well_known_type(ty) {
+                Some(name) => well_known_to_json(&name, ty, value),
+                None => quote! {
+                    #value.to_json_value_with(py, preserving_proto_field_name, including_default_value_fields)
+                },
+            },
+        };
+        quote! {{
+            let json: pyo3::PyResult<pyo3::PyObject> = #json;
+            json
+        }}
+    }
+
+    // Expression converting the Python object `value` of the proto3 JSON representation into this type.
+    fn parse_json(&self, ty: &Type, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let parsed = match self {
+            Self::Scalar(scalar) => scalar_from_json(scalar, value),
+            Self::Enumeration(path) => quote! {
+                match #value.downcast::<pyo3::types::PyString>() {
+                    Ok(name) => <#path>::from_str_name(&name.to_cow()?).map(|variant| variant as i32).ok_or_else(|| {
+                        pyo3::exceptions::PyValueError::new_err(format!(
+                            "invalid enum value {} for enum type {}",
+                            name,
+                            stringify!(#path)
+                        ))
+                    }),
+                    Err(_) => #value.extract::<i32>(),
+                }
+            },
+            Self::Message => match well_known_type(ty) {
+                Some(name) => well_known_from_json(&name, ty, value),
+                None => quote! { <#ty>::from_json_value(#value) },
+            },
+        };
+        quote! {{
+            let parsed: pyo3::PyResult<_> = #parsed;
+            parsed
+        }}
+    }
+}
+
+fn scalar_to_json(scalar: &str, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match scalar {
+        // 64 bit integers are strings, since JSON numbers lose their precision
+        "int64" | "uint64" | "sint64" | "fixed64" | "sfixed64" => quote! { Ok(#value.to_string().into_py(py)) },
+        "float" | "double" => {
+            // Floats are widened by their shortest representation, e.g. `0.1f32` becomes `0.1`
+            let number = if scalar == "float" {
+                quote! { #value.to_string().parse::<f64>().unwrap_or_default() }
+            } else {
+                quote! { *#value }
+            };
+            quote! {{
+                let number: f64 = #number;
+                if number.is_nan() {
+                    Ok("NaN".into_py(py))
+                } else if number.is_infinite() {
+                    Ok(if number > 0.0 { "Infinity" } else { "-Infinity" }.into_py(py))
+                } else {
+                    Ok(number.into_py(py))
+                }
+            }}
+        }
+        "string" => quote! { Ok(#value.as_str().into_py(py)) },
+        "bytes" => quote! {
+            py.import_bound("base64")?
+                .call_method1("b64encode", (pyo3::types::PyBytes::new_bound(py, #value),))?
+                .call_method0("decode")
+                .map(pyo3::Bound::unbind)
+        },
+        _ => quote! { Ok((*#value).into_py(py)) },
+    }
+}
+
+fn scalar_from_json(scalar: &str, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let integer = match scalar {
+        "int32" | "sint32" | "sfixed32" => Some(quote! { i32 }),
+        "uint32" | "fixed32" => Some(quote! { u32 }),
+        "int64" | "sint64" | "sfixed64" => Some(quote! { i64 }),
+        "uint64" | "fixed64" => Some(quote! { u64 }),
+        _ => None,
     };
+    if let Some(integer) = integer {
+        // Integers may be quoted
+        return quote! {
+            match #value.downcast::<pyo3::types::PyString>() {
+                Ok(text) => text.to_cow()?.parse::<#integer>().map_err(|err| {
+                    pyo3::exceptions::PyValueError::new_err(format!("invalid {} {:?}: {}", #scalar, text.to_string(), err))
+                }),
+                Err(_) => #value.extract::<#integer>(),
+            }
+        };
+    }
+    match scalar {
+        "float" | "double" => {
+            let float = format_ident!("f{}", if scalar == "float" { "32" } else { "64" });
+            // Also accepts "NaN", "Infinity" and "-Infinity"
+            quote! {
+                match #value.downcast::<pyo3::types::PyString>() {
+                    Ok(text) => text.to_cow()?.parse::<#float>().map_err(|err| {
+                        pyo3::exceptions::PyValueError::new_err(format!("invalid {} {:?}: {}", #scalar, text.to_string(), err))
+                    }),
+                    Err(_) => #value.extract::<f64>().map(|number| number as #float),
+                }
+            }
+        }
+        // Standard and URL-safe base64, with or without padding
+        "bytes" => quote! {
+            #value.extract::<String>().and_then(|text| {
+                let text = text.trim_end_matches('=').replace('+', "-").replace('/', "_");
+                let padding = "=".repeat((4 - text.len() % 4) % 4);
+                let decoded = py.import_bound("base64")?.call_method1("urlsafe_b64decode", (text + &padding,))?;
+                Ok(decoded.downcast::<pyo3::types::PyBytes>()?.as_bytes().to_vec().into())
+            })
+        },
+        "string" => quote! { #value.extract::<String>() },
+        _ => quote! { #value.extract::<bool>() },
+    }
+}
 
-    output.into()
+// Name of a `google.protobuf` well-known type with a special JSON representation, e.g. `Timestamp`
+// of `::prost_types::Timestamp`.
+fn well_known_type(ty: &Type) -> Option<String> {
+    const WELL_KNOWN_TYPES: [&str; 16] = [
+        "Any", "Duration", "Timestamp", "Empty", "FieldMask", "Struct", "Value", "ListValue", "DoubleValue",
+        "FloatValue", "Int64Value", "UInt64Value", "Int32Value", "UInt32Value", "BoolValue", "StringValue",
+    ];
+    match ty {
+        Type::Path(type_path) => {
+            let segments = &type_path.path.segments;
+            let name = segments.last()?.ident.to_string();
+            let is_well_known = segments.iter().any(|segment| segment.ident == "prost_types" || segment.ident == "protobuf")
+                && (WELL_KNOWN_TYPES.contains(&name.as_str()) || name == "BytesValue");
+            is_well_known.then_some(name)
+        }
+        _ => None,
+    }
+}
+
+// Scalar type wrapped by the well-known type `name`, e.g. `int64` for `Int64Value`.
+fn wrapped_scalar(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "DoubleValue" => "double",
+        "FloatValue" => "float",
+        "Int64Value" => "int64",
+        "UInt64Value" => "uint64",
+        "Int32Value" => "int32",
+        "UInt32Value" => "uint32",
+        "BoolValue" => "bool",
+        "StringValue" => "string",
+        "BytesValue" => "bytes",
+        _ => return None,
+    })
+}
+
+// Module of the well-known type `ty`, e.g. `::prost_types` of `::prost_types::Timestamp`.
+fn well_known_module(ty: &Type) -> proc_macro2::TokenStream {
+    match ty {
+        Type::Path(type_path) => {
+            let mut path = type_path.path.clone();
+            path.segments.pop();
+            // Drops the trailing `::` left by `pop()`
+            let segments = path.segments.iter();
+            let leading_colon = path.leading_colon;
+            quote! { #leading_colon #(#segments)::* }
+        }
+        _ => quote! {},
+    }
+}
+
+// Fractional seconds of `nanos` with 0, 3, 6 or 9 digits, like the JSON of `Timestamp` and `Duration`.
+fn json_fraction(nanos: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {{
+        let nanos = #nanos;
+        if nanos == 0 {
+            String::new()
+        } else if nanos % 1_000_000 == 0 {
+            format!(".{:03}", nanos / 1_000_000)
+        } else if nanos % 1_000 == 0 {
+            format!(".{:06}", nanos / 1_000)
+        } else {
+            format!(".{:09}", nanos)
+        }
+    }}
 }
 
+// Nanoseconds of the fractional seconds digits `digits`, e.g. `"5"` is `500_000_000`.
+fn json_nanos(digits: proc_macro2::TokenStream, invalid: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {{
+        let digits: &str = #digits;
+        if digits.len() > 9 || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(#invalid);
+        }
+        format!("{:0<9}", digits).parse::<i32>().map_err(|_| #invalid)?
+    }}
+}
 
-// #[proc_macro_attribute]
-// pub fn with_string(_: TokenStream, input: TokenStream) -> TokenStream {
-//     // Parse the input tokens into a syntax tree
-//     let input = parse_macro_input!(input as Item);
-
-//     // Check if the input is a module
-//     if let Item::Mod(item_mod) = input {
-//         let mod_name = &item_mod.ident;
-//         let items = &item_mod.content;
-
-//         let output = if let Some((brace, items)) = items {
-//             quote! {
-//                 pub mod #mod_name {
-//                     use core::str;
-//                     use std::fmt;
-//                     pub use pyo3::prelude::*;
-//                     use pyo3::PyErr;
-//                     use pyo3::exceptions::PyOSError;
-//                     use pyo3::types::{PyBytes};
-//                     use tonic::Status;
-//                     use prost::{DecodeError, EncodeError, Message};
-                    
-                    
-//                     // An error indicates taht failing at serializing object to bytes string, like `SerializTOString()` for python protos.
-//                     pub struct MessageEncodeError(EncodeError);
-//                     // An error indicates taht failing at deserializing object from bytes string, like `ParseFromString()` for python protos.
-//                     pub struct MessageDecodeError(DecodeError);
-
-//                     impl fmt::Display for MessageEncodeError {
-//                         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//                             write!(f, "")
-//                         }
-//                     }
-
-//                     impl fmt::Display for MessageDecodeError {
-//                         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//                             write!(f, "")
-//                         }
-//                     }
-
-//                     impl std::convert::From<MessageEncodeError> for PyErr {
-//                         fn from(err: MessageEncodeError) -> PyErr {
-//                             PyOSError::new_err(err.to_string())
-//                         }
-//                     }
-
-//                     impl std::convert::From<MessageDecodeError> for PyErr {
-//                         fn from(err: MessageDecodeError) -> PyErr {
-//                             PyOSError::new_err(err.to_string())
-//                         }
-//                     }
-
-//                     impl std::convert::From<EncodeError> for MessageEncodeError {
-//                         fn from(other: EncodeError) -> Self {
-//                             Self(other)
-//                         }
-//                     }
-
-//                     impl std::convert::From<DecodeError> for MessageDecodeError {
-//                         fn from(other: DecodeError) -> Self {
-//                             Self(other)
-//                         }
-//                     }
-
-//                     pub trait ProtobufDecoder<T> where T: Message + Default {
-//                         fn decode_proto(&self, bytes_obj: &PyBytes) -> Result<T, MessageDecodeError>;
-//                     }
-                    
-//                     pub trait ProtobufEncoder<T> where T: Message + Default {
-//                         fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError>;
-//                     }
-                    
-//                     impl<T> ProtobufDecoder<T> for T where T: Message + Default {
-//                         fn decode_proto(&self, bytes_obj: &PyBytes) -> Result<T, MessageDecodeError> {
-//                             let bytes = bytes_obj.as_bytes();
-//                             let de = Message::decode(&bytes.to_vec()[..]);
-//                             Ok(de?)
-//                         }
-//                     }
-                    
-//                     impl<T> ProtobufEncoder<T> for T where T: Message + Default {
-//                         fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError> {
-//                             let mut buf = vec![];
-//                             res.encode(&mut buf)?;
-//                             Ok(buf)
-//                         }
-//                     }
-
-//                     use pyo3::prelude::*;
-//                     #(#items)*
-//                 }
-//             }
-//         } else {
-//             // If the module has no content (an external module declaration)
-//             quote! {
-//                 pub mod #mod_name;
-
-//                 use core::str;
-//                     use std::fmt;
-//                     pub use pyo3::prelude::*;
-//                     use pyo3::PyErr;
-//                     use pyo3::exceptions::PyOSError;
-//                     use pyo3::types::{PyBytes};
-//                     use tonic::Status;
-//                     use prost::{DecodeError, EncodeError, Message};
-                    
-                    
-//                     // An error indicates taht failing at serializing object to bytes string, like `SerializTOString()` for python protos.
-//                     pub struct MessageEncodeError(EncodeError);
-//                     // An error indicates taht failing at deserializing object from bytes string, like `ParseFromString()` for python protos.
-//                     pub struct MessageDecodeError(DecodeError);
-
-//                     impl fmt::Display for MessageEncodeError {
-//                         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//                             write!(f, "")
-//                         }
-//                     }
-
-//                     impl fmt::Display for MessageDecodeError {
-//                         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//                             write!(f, "")
-//                         }
-//                     }
-
-//                     impl std::convert::From<MessageEncodeError> for PyErr {
-//                         fn from(err: MessageEncodeError) -> PyErr {
-//                             PyOSError::new_err(err.to_string())
-//                         }
-//                     }
-
-//                     impl std::convert::From<MessageDecodeError> for PyErr {
-//                         fn from(err: MessageDecodeError) -> PyErr {
-//                             PyOSError::new_err(err.to_string())
-//                         }
-//                     }
-
-//                     impl std::convert::From<EncodeError> for MessageEncodeError {
-//                         fn from(other: EncodeError) -> Self {
-//                             Self(other)
-//                         }
-//                     }
-
-//                     impl std::convert::From<DecodeError> for MessageDecodeError {
-//                         fn from(other: DecodeError) -> Self {
-//                             Self(other)
-//                         }
-//                     }
-
-//                     pub trait ProtobufDecoder<T> where T: Message + Default {
-//                         fn decode_proto(&self, bytes_obj: &PyBytes) -> Result<T, MessageDecodeError>;
-//                     }
-                    
-//                     pub trait ProtobufEncoder<T> where T: Message + Default {
-//                         fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError>;
-//                     }
-                    
-//                     impl<T> ProtobufDecoder<T> for T where T: Message + Default {
-//                         fn decode_proto(&self, bytes_obj: &PyBytes) -> Result<T, MessageDecodeError> {
-//                             let bytes = bytes_obj.as_bytes();
-//                             let de = Message::decode(&bytes.to_vec()[..]);
-//                             Ok(de?)
-//                         }
-//                     }
-                    
-//                     impl<T> ProtobufEncoder<T> for T where T: Message + Default {
-//                         fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError> {
-//                             let mut buf = vec![];
-//                             res.encode(&mut buf)?;
-//                             Ok(buf)
-//                         }
-//                     }
-
-//             }
-//         };
-
-//         output.into()
-//     } else {
-//         // If the input is not a module, return it unchanged
-//         quote! { #input }.into()
-//     }
-// }
-
-
-
-// #[proc_macro_attribute]
-// pub fn list_all_async_methods(_: TokenStream, input: TokenStream) -> TokenStream {
-//     let input = parse_macro_input!(input as ItemMod);
-//     let mod_name = &input.ident;
-//     let mut method_tuples = Vec::new();
-//     let mut struct_name = None;
-//     let mut items = vec![];
-//     let mut impl_blocks = vec![];
-
-//     // Extract struct name and async methods
-//     for item in input.content.unwrap().1 {
-//         if let Item::Struct(ItemStruct { ident, .. }) = &item {
-//             struct_name = Some(ident.clone());
-//         }
-
-//         if let Item::Impl(item_impl) = &item {
-//             if let Type::Path(type_path) = &*item_impl.self_ty {
-//                 if struct_name.is_none() {
-//                     struct_name = Some(type_path.path.segments.last().unwrap().ident.clone());
-//                 }
-//                 for impl_item in &item_impl.items {
-//                     if let ImplItem::Method(method) = impl_item {
-//                         if method.sig.asyncness.is_some() {
-//                             let method_name = method.sig.ident.clone();
-//                             let mut inputs = Vec::new();
-
-//                             // Collect input types
-//                             for input in &method.sig.inputs {
-//                                 if let FnArg::Typed(pat_type) = input {
-//                                     inputs.push(deepest_type(&pat_type.ty));
-//                                 }
-//                             }
-
-//                             // Collect return type
-//                             let output = match &method.sig.output {
-//                                 ReturnType::Default => quote! {()},
-//                                 ReturnType::Type(_, ty) => deepest_type(&ty),
-//                             };
-
-//                             let inputs: proc_macro2::TokenStream = quote! { (#(#inputs),*) };
-//                             method_tuples.push((method_name, inputs, output));
-//                         }
-//                     }
-//                 }
-//                 impl_blocks.push(item_impl.clone());
-//                 continue;
-//             }
-//         }
-
-//         items.push(item);
-//     }
-
-//     let struct_name = struct_name.expect("No struct found in the module.");
-
-//     // Generate list_all_async_methods function
-//     let list_all_async_methods_fn = {
-//         let method_names = method_tuples.iter().map(|(name, _, _)| name);
-//         let input_types = method_tuples.iter().map(|(_, inputs, _)| inputs);
-//         let output_types = method_tuples.iter().map(|(_, _, output)| output);
-//         quote! {
-//             pub fn list_all_async_methods() -> Vec<(&'static str, &'static str, &'static str)> {
-//                 vec![
-//                     #((stringify!(#method_names), stringify!(#input_types), stringify!(#output_types))),*
-//                 ]
-//             }
-//         }
-//     };
-
-//     // Add list_all_async_methods function to the last impl block
-//     if let Some(last_impl) = impl_blocks.last_mut() {
-//         last_impl.items.push(ImplItem::Method(syn::parse_quote! {
-//             #list_all_async_methods_fn
-//         }));
-//     }
-
-//     // Reassemble the module with updated impl blocks
-//     items.extend(impl_blocks.into_iter().map(Item::Impl));
-
-//     let output = quote! {
-//         pub mod #mod_name {
-//             #(#items)*
-//         }
-//     };
-
-//     output.into()
-// }
-
-// fn deepest_type(ty: &Type) -> proc_macro2::TokenStream {
-//     match ty {
-//         Type::Path(type_path) => {
-//             if let Some(segment) = type_path.path.segments.last() {
-//                 if segment.arguments.is_empty() {
-//                     return quote! {#type_path};
-//                 } else {
-//                     if let PathArguments::AngleBracketed(args) = &segment.arguments {
-//                         if let Some(GenericArgument::Type(inner_ty)) = args.args.first() {
-//                             return deepest_type(inner_ty);
-//                         }
-//                     }
-//                 }
-//             }
-//         }
-//         _ => {}
-//     }
-//     quote! {#ty}
-// }
\ No newline at end of file
+fn well_known_to_json(name: &str, ty: &Type, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if let Some(scalar) = wrapped_scalar(name) {
+        return scalar_to_json(scalar, quote! { (&#value.value) });
+    }
+    let module = well_known_module(ty);
+    match name {
+        "Timestamp" => {
+            let fraction = json_fraction(quote! { #value.nanos });
+            quote! {{
+                let days = #value.seconds.div_euclid(86_400);
+                let time = #value.seconds.rem_euclid(86_400);
+                // Civil date of the days since 1970-01-01, http://howardhinnant.github.io/date_algorithms.html
+                let era = (days + 719_468).div_euclid(146_097);
+                let day_of_era = (days + 719_468).rem_euclid(146_097);
+                let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+                let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+                let shifted_month = (5 * day_of_year + 2) / 153;
+                let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+                let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+                let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+                Ok(format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+                    year,
+                    month,
+                    day,
+                    time / 3_600,
+                    time % 3_600 / 60,
+                    time % 60,
+                    #fraction
+                )
+                .into_py(py))
+            }}
+        }
+        "Duration" => {
+            let fraction = json_fraction(quote! { #value.nanos.abs() });
+            quote! {{
+                let sign = if #value.seconds < 0 || #value.nanos < 0 { "-" } else { "" };
+                Ok(format!("{}{}{}s", sign, #value.seconds.abs(), #fraction).into_py(py))
+            }}
+        }
+        "Empty" => quote! { Ok(pyo3::types::PyDict::new_bound(py).into_any().unbind()) },
+        "FieldMask" => quote! {{
+            let paths: Vec<String> = #value
+                .paths
+                .iter()
+                .map(|path| {
+                    let mut camel = String::new();
+                    let mut upper = false;
+                    for c in path.chars() {
+                        if c == '_' {
+                            upper = true;
+                        } else if upper {
+                            camel.extend(c.to_uppercase());
+                            upper = false;
+                        } else {
+                            camel.push(c);
+                        }
+                    }
+                    camel
+                })
+                .collect();
+            Ok(paths.join(",").into_py(py))
+        }},
+        "Struct" | "Value" | "ListValue" => {
+            let convert = format_ident!("{}_to_json", snake_case(name));
+            quote! {{
+                fn value_to_json(py: pyo3::Python<'_>, value: &#module::Value) -> pyo3::PyResult<pyo3::PyObject> {
+                    use pyo3::IntoPy;
+                    Ok(match &value.kind {
+                        Some(#module::value::Kind::NumberValue(number)) => (*number).into_py(py),
+                        Some(#module::value::Kind::StringValue(text)) => text.as_str().into_py(py),
+                        Some(#module::value::Kind::BoolValue(flag)) => (*flag).into_py(py),
+                        Some(#module::value::Kind::StructValue(fields)) => struct_to_json(py, fields)?,
+                        Some(#module::value::Kind::ListValue(values)) => list_value_to_json(py, values)?,
+                        Some(#module::value::Kind::NullValue(_)) | None => py.None(),
+                    })
+                }
+
+                fn struct_to_json(py: pyo3::Python<'_>, value: &#module::Struct) -> pyo3::PyResult<pyo3::PyObject> {
+                    use pyo3::prelude::*;
+                    let dict = pyo3::types::PyDict::new_bound(py);
+                    for (key, field) in &value.fields {
+                        dict.set_item(key, value_to_json(py, field)?)?;
+                    }
+                    Ok(dict.into_any().unbind())
+                }
+
+                fn list_value_to_json(py: pyo3::Python<'_>, value: &#module::ListValue) -> pyo3::PyResult<pyo3::PyObject> {
+                    use pyo3::prelude::*;
+                    let list = pyo3::types::PyList::empty_bound(py);
+                    for item in &value.values {
+                        list.append(value_to_json(py, item)?)?;
+                    }
+                    Ok(list.into_any().unbind())
+                }
+
+                #convert(py, #value)
+            }}
+        }
+        // `Any` needs a type registry to resolve its `type_url`
+        _ => quote! {
+            Err(pyo3::exceptions::PyNotImplementedError::new_err(format!(
+                "JSON mapping of google.protobuf.{} is not supported",
+                #name
+            )))
+        },
+    }
+}
+
+fn well_known_from_json(name: &str, ty: &Type, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if let Some(scalar) = wrapped_scalar(name) {
+        let wrapped = scalar_from_json(scalar, value);
+        return quote! { (#wrapped).map(|value| #ty { value }) };
+    }
+    let module = well_known_module(ty);
+    let invalid = quote! { pyo3::exceptions::PyValueError::new_err(format!("invalid {} {:?}", #name, text)) };
+    match name {
+        "Timestamp" => {
+            let nanos = json_nanos(quote! { &fraction[1..] }, invalid.clone());
+            quote! {
+                #value.extract::<String>().and_then(|text| {
+                    let number = |start: usize, end: usize| {
+                        text.get(start..end)
+                            .filter(|digits| digits.bytes().all(|digit| digit.is_ascii_digit()))
+                            .and_then(|digits| digits.parse::<i64>().ok())
+                            .ok_or_else(|| #invalid)
+                    };
+                    if !text.is_ascii() || text.len() < 20 || &text[4..5] != "-" || &text[7..8] != "-" || !text[10..11].eq_ignore_ascii_case("t")
+                        || &text[13..14] != ":" || &text[16..17] != ":"
+                    {
+                        return Err(#invalid);
+                    }
+                    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+                    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+                    let zone_start = text[19..].find(|c: char| c == 'Z' || c == 'z' || c == '+' || c == '-').ok_or_else(|| #invalid)? + 19;
+                    let fraction = &text[19..zone_start];
+                    let nanos = if fraction.is_empty() {
+                        0
+                    } else if fraction.starts_with('.') && fraction.len() > 1 {
+                        #nanos
+                    } else {
+                        return Err(#invalid);
+                    };
+                    let offset = match &text[zone_start..] {
+                        "Z" | "z" => 0,
+                        zone if zone.len() == 6 && &zone[3..4] == ":" => {
+                            let offset = number(zone_start + 1, zone_start + 3)? * 3_600 + number(zone_start + 4, zone_start + 6)? * 60;
+                            if zone.starts_with('-') { -offset } else { offset }
+                        }
+                        _ => return Err(#invalid),
+                    };
+                    // Days since 1970-01-01 of the civil date, http://howardhinnant.github.io/date_algorithms.html
+                    let shifted_year = if month <= 2 { year - 1 } else { year };
+                    let era = shifted_year.div_euclid(400);
+                    let year_of_era = shifted_year - era * 400;
+                    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+                    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+                    let days = era * 146_097 + day_of_era - 719_468;
+                    Ok(#ty {
+                        seconds: days * 86_400 + hour * 3_600 + minute * 60 + second - offset,
+                        nanos,
+                    })
+                })
+            }
+        }
+        "Duration" => {
+            let nanos = json_nanos(quote! { fraction }, invalid.clone());
+            quote! {
+                #value.extract::<String>().and_then(|text| {
+                    let duration = text.strip_suffix('s').ok_or_else(|| #invalid)?;
+                    let (negative, duration) = match duration.strip_prefix('-') {
+                        Some(duration) => (true, duration),
+                        None => (false, duration),
+                    };
+                    let (seconds, fraction) = duration.split_once('.').unwrap_or((duration, ""));
+                    if seconds.is_empty() || !seconds.bytes().all(|digit| digit.is_ascii_digit()) {
+                        return Err(#invalid);
+                    }
+                    let seconds = seconds.parse::<i64>().map_err(|_| #invalid)?;
+                    let nanos = #nanos;
+                    Ok(if negative {
+                        #ty { seconds: -seconds, nanos: -nanos }
+                    } else {
+                        #ty { seconds, nanos }
+                    })
+                })
+            }
+        }
+        "Empty" => quote! { Ok(#ty {}) },
+        "FieldMask" => quote! {
+            #value.extract::<String>().map(|text| #ty {
+                paths: text
+                    .split(',')
+                    .filter(|path| !path.is_empty())
+                    .map(|path| {
+                        let mut snake = String::new();
+                        for c in path.chars() {
+                            if c.is_uppercase() {
+                                snake.push('_');
+                                snake.extend(c.to_lowercase());
+                            } else {
+                                snake.push(c);
+                            }
+                        }
+                        snake
+                    })
+                    .collect(),
+            })
+        },
+        "Struct" | "Value" | "ListValue" => {
+            let convert = format_ident!("{}_from_json", snake_case(name));
+            quote! {{
+                fn value_from_json(value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<#module::Value> {
+                    use pyo3::prelude::*;
+                    let kind = if value.is_none() {
+                        #module::value::Kind::NullValue(0)
+                    } else if let Ok(flag) = value.downcast::<pyo3::types::PyBool>() {
+                        #module::value::Kind::BoolValue(flag.is_true())
+                    } else if let Ok(text) = value.downcast::<pyo3::types::PyString>() {
+                        #module::value::Kind::StringValue(text.to_cow()?.into_owned())
+                    } else if value.is_instance_of::<pyo3::types::PyDict>() {
+                        #module::value::Kind::StructValue(struct_from_json(value)?)
+                    } else if value.is_instance_of::<pyo3::types::PyList>() {
+                        #module::value::Kind::ListValue(list_value_from_json(value)?)
+                    } else {
+                        #module::value::Kind::NumberValue(value.extract::<f64>()?)
+                    };
+                    Ok(#module::Value { kind: Some(kind) })
+                }
+
+                fn struct_from_json(value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<#module::Struct> {
+                    use pyo3::prelude::*;
+                    let fields = value
+                        .downcast::<pyo3::types::PyDict>()?
+                        .iter()
+                        .map(|(key, field)| Ok((key.extract::<String>()?, value_from_json(&field)?)))
+                        .collect::<pyo3::PyResult<_>>()?;
+                    Ok(#module::Struct { fields })
+                }
+
+                fn list_value_from_json(value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<#module::ListValue> {
+                    use pyo3::prelude::*;
+                    let values = value
+                        .downcast::<pyo3::types::PyList>()?
+                        .iter()
+                        .map(|item| value_from_json(&item))
+                        .collect::<pyo3::PyResult<_>>()?;
+                    Ok(#module::ListValue { values })
+                }
+
+                #convert(#value)
+            }}
+        }
+        _ => quote! {
+            Err(pyo3::exceptions::PyNotImplementedError::new_err(format!(
+                "JSON mapping of google.protobuf.{} is not supported",
+                #name
+            )))
+        },
+    }
+}
+
+// `to_json_value()` / `from_json_value()` converting a message from and into its proto3 JSON representation
+// as Python objects, and the `to_json()` / `from_json()` methods on top of them, like `json_format` of python
+// protobuf. Fields are named in lowerCamelCase, and parsed by it or their original name. Sub-messages and
+// `oneof` enums have to derive `WithNew` too.
+fn json_methods(name: &syn::Ident, fields: &Fields) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    use syn::ext::IdentExt;
+    let mut to_json = Vec::new();
+    let mut from_json = Vec::new();
+    let mut oneofs = Vec::new();
+    for field in fields.iter() {
+        let field_name = field.ident.as_ref().unwrap();
+        let proto_name = field_name.unraw().to_string();
+        let json_name = lower_camel_case(&proto_name);
+        let key = quote! { if preserving_proto_field_name { #proto_name } else { #json_name } };
+        let attrs = prost_attrs(&field.attrs);
+        let is = |label: &str| attrs.iter().any(|(key, _)| key == label);
+        let ty = &field.ty;
+
+        if is("oneof") {
+            let oneof_type = optional_type(ty).unwrap_or(ty);
+            to_json.push(quote! {
+                if let Some(variant) = &self.#field_name {
+                    let (json_name, value) =
+                        variant.to_json_field(py, preserving_proto_field_name, including_default_value_fields)?;
+                    dict.set_item(json_name, value)?;
+                }
+            });
+            oneofs.push(quote! {
+                if let Some(variant) = <#oneof_type>::from_json_field(key, value)? {
+                    message.#field_name = Some(variant);
+                    return Ok(true);
+                }
+            });
+            continue;
+        }
+
+        let (json, parse) = if let Some((_, Some(map))) = attrs.iter().find(|(key, _)| PROST_MAPS.contains(&key.as_str())) {
+            let (key_type, value_type) = map.split_once(',').unwrap_or((map, ""));
+            let value_type = match ProtoType::from_map_value(value_type) {
+                Some(value_type) => value_type,
+                None => continue,
+            };
+            let generics = generic_types(ty);
+            let element = generics.get(1).copied().unwrap_or(ty);
+            let value_json = value_type.json_of(element, quote! { value });
+            let value_parse = value_type.parse_json(element, quote! { (&value) });
+            // Map keys are strings in JSON
+            let key_parse = if key_type.trim() == "string" {
+                quote! { key }
+            } else {
+                quote! {
+                    key.parse().map_err(|_| pyo3::exceptions::PyValueError::new_err(format!("invalid map key {:?}", key)))?
+                }
+            };
+            (
+                quote! {
+                    if including_default_value_fields || !self.#field_name.is_empty() {
+                        let map = pyo3::types::PyDict::new_bound(py);
+                        for (key, value) in &self.#field_name {
+                            map.set_item(key.to_string(), #value_json?)?;
+                        }
+                        dict.set_item(#key, map)?;
+                    }
+                },
+                quote! {
+                    for (key, value) in value.downcast::<pyo3::types::PyDict>()? {
+                        let key: String = key.extract()?;
+                        message.#field_name.insert(#key_parse, #value_parse?);
+                    }
+                },
+            )
+        } else {
+            let proto_type = match ProtoType::from_attrs(&field.attrs) {
+                Some(proto_type) => proto_type,
+                // Fields which aren't part of the protobuf message
+                None => continue,
+            };
+            if is("repeated") {
+                let element = generic_types(ty).first().copied().unwrap_or(ty);
+                let value_json = proto_type.json_of(element, quote! { value });
+                let value_parse = proto_type.parse_json(element, quote! { (&value) });
+                (
+                    quote! {
+                        if including_default_value_fields || !self.#field_name.is_empty() {
+                            let list = pyo3::types::PyList::empty_bound(py);
+                            for value in &self.#field_name {
+                                list.append(#value_json?)?;
+                            }
+                            dict.set_item(#key, list)?;
+                        }
+                    },
+                    quote! {
+                        for value in value.downcast::<pyo3::types::PyList>()? {
+                            message.#field_name.push(#value_parse?);
+                        }
+                    },
+                )
+            } else if let Some(inner) = optional_type(ty) {
+                let element = boxed_type(inner).unwrap_or(inner);
+                let value_json = proto_type.json_of(element, quote! { value });
+                let value_parse = proto_type.parse_json(element, quote! { (&value) });
+                let parsed = if boxed_type(inner).is_some() {
+                    quote! { ::std::boxed::Box::new(#value_parse?) }
+                } else {
+                    quote! { #value_parse? }
+                };
+                (
+                    quote! {
+                        // Unset fields with presence are left out even with `including_default_value_fields`
+                        if let Some(value) = &self.#field_name {
+                            dict.set_item(#key, #value_json?)?;
+                        }
+                    },
+                    quote! { message.#field_name = Some(#parsed); },
+                )
+            } else {
+                let value_json = proto_type.json_of(ty, quote! { (&self.#field_name) });
+                let value_parse = proto_type.parse_json(ty, quote! { (&value) });
+                (
+                    quote! {
+                        if including_default_value_fields || self.#field_name != <#ty as ::std::default::Default>::default() {
+                            dict.set_item(#key, #value_json?)?;
+                        }
+                    },
+                    quote! { message.#field_name = #value_parse?; },
+                )
+            }
+        };
+        to_json.push(json);
+        from_json.push(quote! {
+            if key == #json_name || key == #proto_name {
+                #parse
+                return Ok(true);
+            }
+        });
+    }
+
+    let json_impl = quote! {
+        impl #name {
+            // Proto3 JSON representation of the message as Python object.
+            pub fn to_json_value(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<pyo3::PyObject> {
+                self.to_json_value_with(py, false, false)
+            }
+
+            // Proto3 JSON representation of the message as Python object, with the fields named as in the
+            // `.proto` file if `preserving_proto_field_name`, and fields without presence set to their default
+            // values included if `including_default_value_fields`, like `json_format.MessageToDict()`.
+            #[allow(unused_variables)]
+            pub fn to_json_value_with(
+                &self,
+                py: pyo3::Python<'_>,
+                preserving_proto_field_name: bool,
+                including_default_value_fields: bool,
+            ) -> pyo3::PyResult<pyo3::PyObject> {
+                use pyo3::prelude::*;
+                use pyo3::IntoPy;
+                let dict = pyo3::types::PyDict::new_bound(py);
+                #(#to_json)*
+                Ok(dict.into_any().unbind())
+            }
+
+            // Message of its proto3 JSON representation as Python object, `null` fields are left unset.
+            pub fn from_json_value(value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<Self> {
+                use pyo3::prelude::*;
+                let mut message = <Self as ::std::default::Default>::default();
+                for (key, value) in value.downcast::<pyo3::types::PyDict>()? {
+                    let key: String = key.extract()?;
+                    if value.is_none() {
+                        continue;
+                    }
+                    // Values of the wrong JSON type are raised as `ValueError` naming the field, like
+                    // `json_format.ParseError`, instead of the `TypeError` / `OverflowError` of their conversion
+                    let merged = message.merge_json_field(&key, &value).map_err(|err| {
+                        pyo3::exceptions::PyValueError::new_err(format!("Failed to parse {} field: {}", key, err))
+                    })?;
+                    if !merged {
+                        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                            "Message type \"{}\" has no field named \"{}\".",
+                            stringify!(#name),
+                            key
+                        )));
+                    }
+                }
+                Ok(message)
+            }
+
+            // Sets the field of the JSON key `key` to `value`, returns whether the message has such a field.
+            #[allow(unused_variables, unreachable_code)]
+            fn merge_json_field(&mut self, key: &str, value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<bool> {
+                use pyo3::prelude::*;
+                let py = value.py();
+                let message = self;
+                #(#from_json)*
+                #(#oneofs)*
+                Ok(false)
+            }
+        }
+    };
+
+    let json_methods = quote! {
+        // Mirrors `json_format.MessageToJson()` of python protobuf.
+        #[pyo3(signature = (indent = Some(2), preserving_proto_field_name = false, including_default_value_fields = false))]
+        pub fn to_json(
+            &self,
+            py: pyo3::Python<'_>,
+            indent: Option<usize>,
+            preserving_proto_field_name: bool,
+            including_default_value_fields: bool,
+        ) -> pyo3::PyResult<String> {
+            use pyo3::prelude::*;
+            let kwargs = pyo3::types::PyDict::new_bound(py);
+            kwargs.set_item("indent", indent)?;
+            kwargs.set_item("ensure_ascii", false)?;
+            let value = self.to_json_value_with(py, preserving_proto_field_name, including_default_value_fields)?;
+            py.import_bound("json")?.call_method("dumps", (value,), Some(&kwargs))?.extract()
+        }
+
+        // Mirrors `json_format.Parse()` of python protobuf, returns the parsed message.
+        #[staticmethod]
+        pub fn from_json(py: pyo3::Python<'_>, json: &str) -> pyo3::PyResult<Self> {
+            use pyo3::prelude::*;
+            let value = py.import_bound("json")?.call_method1("loads", (json,))?;
+            Self::from_json_value(&value)
+        }
+
+        // Mirrors `json_format.MessageToDict()` of python protobuf.
+        #[pyo3(signature = (preserving_proto_field_name = false, including_default_value_fields = false))]
+        pub fn to_dict(
+            &self,
+            py: pyo3::Python<'_>,
+            preserving_proto_field_name: bool,
+            including_default_value_fields: bool,
+        ) -> pyo3::PyResult<pyo3::PyObject> {
+            self.to_json_value_with(py, preserving_proto_field_name, including_default_value_fields)
+        }
+
+        // Mirrors `json_format.ParseDict()` of python protobuf, returns the parsed message. Fields may be named
+        // in lowerCamelCase or as in the `.proto` file.
+        #[staticmethod]
+        pub fn from_dict(d: &pyo3::Bound<'_, pyo3::types::PyDict>) -> pyo3::PyResult<Self> {
+            Self::from_json_value(d.as_any())
+        }
+    };
+
+    (json_impl, json_methods)
+}
+
+// `to_json_field()` / `from_json_field()` converting the variants of prost `oneof`s from and into the
+// `(name, value)` of their field in the proto3 JSON representation of the message.
+fn oneof_json_methods(data: &syn::DataEnum) -> proc_macro2::TokenStream {
+    let mut to_json = Vec::new();
+    let mut from_json = Vec::new();
+    for variant in &data.variants {
+        let proto_type = match ProtoType::from_attrs(&variant.attrs) {
+            Some(proto_type) => proto_type,
+            None => return quote! {},
+        };
+        let variant_ident = &variant.ident;
+        let proto_name = snake_case(&variant_ident.to_string());
+        let json_name = lower_camel_case(&proto_name);
+        let ty = &variant.fields.iter().next().unwrap().ty;
+        let element = boxed_type(ty).unwrap_or(ty);
+        let value_json = proto_type.json_of(element, quote! { value });
+        let value_parse = proto_type.parse_json(element, quote! { value });
+        let parsed = if boxed_type(ty).is_some() {
+            quote! { ::std::boxed::Box::new(#value_parse?) }
+        } else {
+            quote! { #value_parse? }
+        };
+        to_json.push(quote! {
+            Self::#variant_ident(value) => (if preserving_proto_field_name { #proto_name } else { #json_name }, #value_json?),
+        });
+        from_json.push(quote! {
+            if field == #json_name || field == #proto_name {
+                return Ok(Some(Self::#variant_ident(#parsed)));
+            }
+        });
+    }
+
+    quote! {
+        // `(name, value)` of the variant, with the options of `to_json_value_with()` of the message.
+        #[allow(unused_variables)]
+        pub fn to_json_field(
+            &self,
+            py: pyo3::Python<'_>,
+            preserving_proto_field_name: bool,
+            including_default_value_fields: bool,
+        ) -> pyo3::PyResult<(&'static str, pyo3::PyObject)> {
+            use pyo3::prelude::*;
+            use pyo3::IntoPy;
+            Ok(match self {
+                #(#to_json)*
+            })
+        }
+
+        // Variant of the JSON field `field`, if it is one of the `oneof`.
+        #[allow(unused_variables)]
+        pub fn from_json_field(field: &str, value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<Option<Self>> {
+            use pyo3::prelude::*;
+            let py = value.py();
+            #(#from_json)*
+            Ok(None)
+        }
+    }
+}
+
+// `__repr__()` in the form of the constructor call `Name(field=..., ...)` with the fields `fields` of the struct
+// `slf` or its field `field`, like `.0` for wrapper classes. Set variants of the `oneof`s `oneofs` are passed
+// as keyword arguments of their own, like the constructors of messages take them.
+fn repr_method(
+    fields: &[&Option<syn::Ident>],
+    oneofs: &[Option<syn::Ident>],
+    field: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    use syn::ext::IdentExt;
+    let names: Vec<_> = fields.iter().map(|name| name.as_ref().unwrap().unraw().to_string()).collect();
+    quote! {
+        pub fn __repr__(slf: &pyo3::Bound<'_, Self>) -> pyo3::PyResult<String> {
+            use pyo3::prelude::*;
+            let py = slf.py();
+            let this = slf.borrow();
+            let mut args: Vec<String> = Vec::new();
+            #(
+                let value = pyo3::IntoPy::<pyo3::PyObject>::into_py(this #field.#fields.clone(), py);
+                args.push(format!("{}={}", #names, value.bind(py).repr()?));
+            )*
+            #(
+                if let Some(variant) = &this #field.#oneofs {
+                    args.push(format!("{}={}", variant.py_oneof_field(), variant.py_oneof_value(py).bind(py).repr()?));
+                }
+            )*
+            Ok(format!("{}({})", slf.get_type().qualname()?, args.join(", ")))
+        }
+    }
+}
+
+// Tag of a field or `oneof` variant, from its `#[prost(tag = "1")]` attribute.
+fn prost_tag(attrs: &[Attribute]) -> Option<u32> {
+    prost_attrs(attrs)
+        .into_iter()
+        .find(|(key, _)| key == "tag")
+        .and_then(|(_, tag)| tag?.parse().ok())
+}
+
+// Statements writing the field `name` holding `value`, a reference to a value of type `proto_type`, as
+// protobuf text format line(s) indented by `indent` into `out`. Messages are written as `name { ... }` blocks.
+fn text_field(proto_type: &ProtoType, ty: &Type, name: &str, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match proto_type {
+        ProtoType::Scalar(scalar) => text_line(name, scalar_text(scalar, value)),
+        ProtoType::Enumeration(path) => text_line(
+            name,
+            quote! {
+                match <#path>::try_from(*#value) {
+                    Ok(variant) => variant.as_str_name().to_string(),
+                    Err(_) => #value.to_string(),
+                }
+            },
+        ),
+        ProtoType::Message => {
+            let body = match well_known_type(ty) {
+                Some(well_known) => well_known_text(&well_known, ty, value),
+                None => quote! { #value.write_text(out, indent); },
+            };
+            text_block(name, body)
+        }
+    }
+}
+
+// Statement writing the line `name: text`.
+fn text_line(name: &str, text: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
+        out.push_str(&format!("{:indent$}{}: {}\n", "", #name, #text, indent = indent));
+    }
+}
+
+// Statements writing the block `name { ... }`, whose lines are written by `body` with a deeper `indent`.
+fn text_block(name: &str, body: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
+        out.push_str(&format!("{:indent$}{} {{\n", "", #name, indent = indent));
+        {
+            let indent = indent + 2;
+            #body
+        }
+        out.push_str(&format!("{:indent$}}}\n", "", indent = indent));
+    }
+}
+
+// Text of the scalar `value` like python protobuf prints it, e.g. `"caf\303\251"` for `"café"`.
+fn scalar_text(scalar: &str, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match scalar {
+        "float" => text_float(quote! { #value.to_string().parse::<f64>().unwrap_or_default() }),
+        "double" => text_float(quote! { *#value }),
+        "bool" => quote! { if *#value { "true" } else { "false" } },
+        "string" => text_escape(quote! { #value.as_bytes().iter() }),
+        "bytes" => text_escape(quote! { #value.iter() }),
+        _ => quote! { #value },
+    }
+}
+
+// Python `repr()` of the float `number`, e.g. `1e+16`, `inf` and `nan`. Rust and Python switch to
+// scientific notation at the same exponents, but Python always signs the exponent and pads it to 2 digits.
+fn text_float(number: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {{
+        let number: f64 = #number;
+        if number.is_nan() {
+            "nan".to_string()
+        } else if number.is_infinite() {
+            if number > 0.0 { "inf" } else { "-inf" }.to_string()
+        } else {
+            let text = format!("{:?}", number);
+            match text.split_once('e') {
+                Some((mantissa, exponent)) => {
+                    let exponent: i32 = exponent.parse().unwrap_or_default();
+                    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+                }
+                None => text,
+            }
+        }
+    }}
+}
+
+// Quoted `bytes`, an iterator over `&u8`, with C escapes and non-printable bytes as octal escapes,
+// like `text_encoding.CEscape()` of python protobuf.
+fn text_escape(bytes: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {{
+        let mut text = String::from("\"");
+        for &byte in #bytes {
+            match byte {
+                b'\n' => text.push_str("\\n"),
+                b'\r' => text.push_str("\\r"),
+                b'\t' => text.push_str("\\t"),
+                b'"' => text.push_str("\\\""),
+                b'\'' => text.push_str("\\'"),
+                b'\\' => text.push_str("\\\\"),
+                0x20..=0x7e => text.push(byte as char),
+                _ => text.push_str(&format!("\\{:03o}", byte)),
+            }
+        }
+        text.push('"');
+        text
+    }}
+}
+
+// Statements writing the fields of the well-known type `name` in `value`. Well-known types have no special
+// text format, but they aren't `WithNew` types, so their fields are written here.
+fn well_known_text(name: &str, ty: &Type, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if let Some(scalar) = wrapped_scalar(name) {
+        let line = text_line("value", scalar_text(scalar, quote! { (&#value.value) }));
+        return quote! {
+            fn is_default<T: ::std::default::Default + PartialEq>(value: &T) -> bool {
+                *value == T::default()
+            }
+            if !is_default(&#value.value) {
+                #line
+            }
+        };
+    }
+    let module = well_known_module(ty);
+    match name {
+        "Timestamp" | "Duration" => {
+            let seconds = text_line("seconds", quote! { #value.seconds });
+            let nanos = text_line("nanos", quote! { #value.nanos });
+            quote! {
+                if #value.seconds != 0 {
+                    #seconds
+                }
+                if #value.nanos != 0 {
+                    #nanos
+                }
+            }
+        }
+        "FieldMask" => {
+            let line = text_line("paths", scalar_text("string", quote! { path }));
+            quote! {
+                for path in &#value.paths {
+                    #line
+                }
+            }
+        }
+        "Struct" | "Value" | "ListValue" => {
+            let key = text_line("key", scalar_text("string", quote! { key }));
+            let entry = text_block("value", quote! { write_value(value, out, indent); });
+            let fields = text_block("fields", quote! { #key #entry });
+            let values = text_block("values", quote! { write_value(value, out, indent); });
+            let null_value = text_line("null_value", quote! { if *value == 0 { "NULL_VALUE".to_string() } else { value.to_string() } });
+            let number_value = text_line("number_value", scalar_text("double", quote! { value }));
+            let string_value = text_line("string_value", scalar_text("string", quote! { value }));
+            let bool_value = text_line("bool_value", scalar_text("bool", quote! { value }));
+            let struct_value = text_block("struct_value", quote! { write_struct(value, out, indent); });
+            let list_value = text_block("list_value", quote! { write_list(value, out, indent); });
+            let write = match name {
+                "Struct" => quote! { write_struct },
+                "Value" => quote! { write_value },
+                _ => quote! { write_list },
+            };
+            quote! {{
+                fn write_struct(value: &#module::Struct, out: &mut String, indent: usize) {
+                    for (key, value) in &value.fields {
+                        #fields
+                    }
+                }
+                fn write_value(value: &#module::Value, out: &mut String, indent: usize) {
+                    use #module::value::Kind;
+                    match &value.kind {
+                        Some(Kind::NullValue(value)) => { #null_value }
+                        Some(Kind::NumberValue(value)) => { #number_value }
+                        Some(Kind::StringValue(value)) => { #string_value }
+                        Some(Kind::BoolValue(value)) => { #bool_value }
+                        Some(Kind::StructValue(value)) => { #struct_value }
+                        Some(Kind::ListValue(value)) => { #list_value }
+                        None => {}
+                    }
+                }
+                fn write_list(value: &#module::ListValue, out: &mut String, indent: usize) {
+                    for value in &value.values {
+                        #values
+                    }
+                }
+                #write(#value, out, indent);
+            }}
+        }
+        // Without descriptors, the message packed into `Any` can't be expanded
+        "Any" => {
+            let type_url = text_line("type_url", scalar_text("string", quote! { (&#value.type_url) }));
+            let packed = text_line("value", scalar_text("bytes", quote! { (&#value.value) }));
+            quote! {
+                if !#value.type_url.is_empty() {
+                    #type_url
+                }
+                if !#value.value.is_empty() {
+                    #packed
+                }
+            }
+        }
+        // `Empty`
+        _ => quote! {},
+    }
+}
+
+// Type of the tokens of protobuf text format being parsed.
+fn text_tokens_type() -> proc_macro2::TokenStream {
+    quote! { ::std::iter::Peekable<::std::vec::IntoIter<String>> }
+}
+
+// Private helpers parsing protobuf text format, generated once into the `ProtobufTextParser` of `with_string`
+// modules, or into the types parsing it outside of them. Errors are raised as `ValueError`.
+fn text_parse_helpers() -> proc_macro2::TokenStream {
+    let tokens_type = text_tokens_type();
+    quote! {
+        // Splits protobuf text format into quoted strings, words like names and numbers, and punctuation.
+        fn text_tokens(text: &str) -> pyo3::PyResult<Vec<String>> {
+            let is_word = |c: char| c.is_alphanumeric() || "_.-+".contains(c);
+            let mut tokens = Vec::new();
+            let mut chars = text.char_indices().peekable();
+            while let Some((start, c)) = chars.next() {
+                if c.is_whitespace() {
+                    continue;
+                } else if c == '#' {
+                    // Comments run until the end of the line
+                    while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                } else if c == '"' || c == '\'' {
+                    let mut end = None;
+                    while let Some((index, next)) = chars.next() {
+                        if next == '\\' {
+                            chars.next();
+                        } else if next == c {
+                            end = Some(index + 1);
+                            break;
+                        } else if next == '\n' {
+                            break;
+                        }
+                    }
+                    let end = end.ok_or_else(|| Self::text_error(format!("unterminated string {}", &text[start..])))?;
+                    tokens.push(text[start..end].to_string());
+                } else if is_word(c) {
+                    let mut end = start + c.len_utf8();
+                    while let Some((index, next)) = chars.next_if(|(_, next)| is_word(*next)) {
+                        end = index + next.len_utf8();
+                    }
+                    tokens.push(text[start..end].to_string());
+                } else {
+                    tokens.push(c.to_string());
+                }
+            }
+            Ok(tokens)
+        }
+
+        fn text_error(message: String) -> pyo3::PyErr {
+            pyo3::exceptions::PyValueError::new_err(message)
+        }
+
+        fn text_next(tokens: &mut #tokens_type) -> pyo3::PyResult<String> {
+            tokens.next().ok_or_else(|| Self::text_error("unexpected end of text".to_string()))
+        }
+
+        // Skips the `:` between a field name and its value, which is optional before messages.
+        fn text_colon(tokens: &mut #tokens_type, required: bool) -> pyo3::PyResult<()> {
+            if tokens.next_if(|token| token == ":").is_none() && required {
+                let found = tokens.peek().cloned().unwrap_or_default();
+                return Err(Self::text_error(format!("expected \":\" but found {:?}", found)));
+            }
+            Ok(())
+        }
+
+        // Skips the optional `,` or `;` after a field.
+        fn text_separator(tokens: &mut #tokens_type) {
+            tokens.next_if(|token| token == "," || token == ";");
+        }
+
+        // Consumes the `{` or `<` opening a message and returns the token closing it.
+        fn text_open(tokens: &mut #tokens_type) -> pyo3::PyResult<&'static str> {
+            match Self::text_next(tokens)?.as_str() {
+                "{" => Ok("}"),
+                "<" => Ok(">"),
+                token => Err(Self::text_error(format!("expected \"{{\" but found {:?}", token))),
+            }
+        }
+
+        // Values of a repeated field parsed with `parse`, either one or the list `[a, b]`.
+        fn text_values<T>(
+            tokens: &mut #tokens_type,
+            mut parse: impl FnMut(&mut #tokens_type) -> pyo3::PyResult<T>,
+        ) -> pyo3::PyResult<Vec<T>> {
+            if tokens.next_if(|token| token == "[").is_none() {
+                return Ok(vec![parse(tokens)?]);
+            }
+            let mut values = Vec::new();
+            while tokens.next_if(|token| token == "]").is_none() {
+                values.push(parse(tokens)?);
+                tokens.next_if(|token| token == ",");
+            }
+            Ok(values)
+        }
+
+        // Decimal, hexadecimal `0x1f` or octal `017` integer.
+        fn text_integer<T: TryFrom<i128>>(tokens: &mut #tokens_type) -> pyo3::PyResult<T> {
+            let token = Self::text_next(tokens)?;
+            let (sign, digits) = match token.strip_prefix('-') {
+                Some(digits) => (-1, digits),
+                None => (1, token.as_str()),
+            };
+            let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                i128::from_str_radix(hex, 16)
+            } else if digits.len() > 1 && digits.starts_with('0') {
+                i128::from_str_radix(&digits[1..], 8)
+            } else {
+                digits.parse()
+            };
+            value
+                .ok()
+                .and_then(|value| T::try_from(sign * value).ok())
+                .ok_or_else(|| Self::text_error(format!("invalid integer {:?}", token)))
+        }
+
+        // Floats may have an `f` suffix, and be `inf`, `-inf` or `nan` in any case.
+        fn text_number(tokens: &mut #tokens_type) -> pyo3::PyResult<f64> {
+            let token = Self::text_next(tokens)?;
+            let trimmed = token.strip_suffix(['f', 'F']).filter(|number| number.ends_with(|c: char| c.is_ascii_digit() || c == '.'));
+            trimmed
+                .unwrap_or(&token)
+                .parse()
+                .map_err(|_| Self::text_error(format!("invalid float {:?}", token)))
+        }
+
+        fn text_bool(tokens: &mut #tokens_type) -> pyo3::PyResult<bool> {
+            match Self::text_next(tokens)?.as_str() {
+                "true" | "True" | "t" | "1" => Ok(true),
+                "false" | "False" | "f" | "0" => Ok(false),
+                token => Err(Self::text_error(format!("invalid bool {:?}", token))),
+            }
+        }
+
+        // Bytes of adjacent quoted strings, with C escapes like `\n`, `\303` or `\xc3`.
+        fn text_bytes(tokens: &mut #tokens_type) -> pyo3::PyResult<Vec<u8>> {
+            let mut bytes = Vec::new();
+            let mut quoted = false;
+            while let Some(token) = tokens.next_if(|token| token.starts_with(['"', '\''])) {
+                quoted = true;
+                let mut chars = token[1..token.len() - 1].chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c != '\\' {
+                        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        continue;
+                    }
+                    let escaped = chars.next().unwrap_or_default();
+                    let byte = match escaped {
+                        'n' => b'\n',
+                        'r' => b'\r',
+                        't' => b'\t',
+                        'a' => 0x07,
+                        'b' => 0x08,
+                        'f' => 0x0c,
+                        'v' => 0x0b,
+                        '0'..='7' => {
+                            let mut value = escaped.to_digit(8).unwrap_or_default();
+                            for _ in 0..2 {
+                                match chars.next_if(|c| c.is_digit(8)) {
+                                    Some(digit) => value = value * 8 + digit.to_digit(8).unwrap_or_default(),
+                                    None => break,
+                                }
+                            }
+                            value as u8
+                        }
+                        'x' => {
+                            let mut value = 0;
+                            for _ in 0..2 {
+                                match chars.next_if(|c| c.is_ascii_hexdigit()) {
+                                    Some(digit) => value = value * 16 + digit.to_digit(16).unwrap_or_default(),
+                                    None => break,
+                                }
+                            }
+                            value as u8
+                        }
+                        'u' | 'U' => {
+                            let digits: String = (0..if escaped == 'u' { 4 } else { 8 }).filter_map(|_| chars.next()).collect();
+                            let c = u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| Self::text_error(format!("invalid escape \\{}{} in {}", escaped, digits, token)))?;
+                            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                            continue;
+                        }
+                        '"' | '\'' | '\\' | '?' => escaped as u8,
+                        c => return Err(Self::text_error(format!("invalid escape \\{} in {}", c, token))),
+                    };
+                    bytes.push(byte);
+                }
+            }
+            if !quoted {
+                let found = tokens.peek().cloned().unwrap_or_default();
+                return Err(Self::text_error(format!("expected a string but found {:?}", found)));
+            }
+            Ok(bytes)
+        }
+
+        fn text_string(tokens: &mut #tokens_type) -> pyo3::PyResult<String> {
+            String::from_utf8(Self::text_bytes(tokens)?).map_err(|err| Self::text_error(err.to_string()))
+        }
+
+        // Enum value by name or number.
+        fn text_enum(tokens: &mut #tokens_type, from_name: impl Fn(&str) -> Option<i32>) -> pyo3::PyResult<i32> {
+            match tokens.peek().and_then(|name| from_name(name)) {
+                Some(value) => {
+                    tokens.next();
+                    Ok(value)
+                }
+                None if tokens.peek().is_some_and(|token| token.starts_with(|c: char| c.is_ascii_digit() || c == '-')) => {
+                    Self::text_integer(tokens)
+                }
+                None => Err(Self::text_error(format!("invalid enum value {:?}", tokens.peek().cloned().unwrap_or_default()))),
+            }
+        }
+    }
+}
+
+// Expression parsing a value of type `proto_type` from `tokens` into a `PyResult`, with the helpers of
+// `text_parse_helpers()` implemented by `owner`. `ty` is the Rust type of messages.
+fn text_parse(proto_type: &ProtoType, ty: &Type, owner: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match proto_type {
+        ProtoType::Scalar(scalar) => scalar_from_text(scalar, owner),
+        ProtoType::Enumeration(path) => quote! {
+            <#owner>::text_enum(tokens, |name| <#path>::from_str_name(name).map(|value| value as i32))
+        },
+        ProtoType::Message => match well_known_type(ty) {
+            Some(name) => well_known_from_text(&name, ty, owner),
+            None => quote! {
+                <#owner>::text_open(tokens).and_then(|end| {
+                    let mut message = <#ty as ::std::default::Default>::default();
+                    message.merge_text(tokens, Some(end))?;
+                    Ok(message)
+                })
+            },
+        },
+    }
+}
+
+fn scalar_from_text(scalar: &str, owner: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match scalar {
+        "int32" | "sint32" | "sfixed32" => quote! { <#owner>::text_integer::<i32>(tokens) },
+        "uint32" | "fixed32" => quote! { <#owner>::text_integer::<u32>(tokens) },
+        "int64" | "sint64" | "sfixed64" => quote! { <#owner>::text_integer::<i64>(tokens) },
+        "uint64" | "fixed64" => quote! { <#owner>::text_integer::<u64>(tokens) },
+        "float" => quote! { <#owner>::text_number(tokens).map(|number| number as f32) },
+        "double" => quote! { <#owner>::text_number(tokens) },
+        "bool" => quote! { <#owner>::text_bool(tokens) },
+        "string" => quote! { <#owner>::text_string(tokens) },
+        _ => quote! { <#owner>::text_bytes(tokens).map(Into::into) },
+    }
+}
+
+// Statements parsing the fields of `message`, of the message type `name`, from `tokens` until the token `end`.
+// `fields` are the names of the fields with the statements parsing them.
+fn text_fields_parse(
+    name: &str,
+    fields: Vec<(&str, proc_macro2::TokenStream)>,
+    owner: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let (names, parses): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+    // Any field of a message without fields is an error
+    let separator = if names.is_empty() {
+        quote! {}
+    } else {
+        quote! { <#owner>::text_separator(tokens); }
+    };
+    quote! {
+        loop {
+            let field = <#owner>::text_next(tokens)?;
+            if field == end {
+                break;
+            }
+            match field.as_str() {
+                #(#names => { #parses })*
+                _ => {
+                    return Err(<#owner>::text_error(format!(
+                        "Message type \"{}\" has no field named \"{}\".",
+                        #name, field
+                    )))
+                }
+            }
+            #separator
+        }
+    }
+}
+
+// Expression parsing the well-known type `name` field-wise, since it isn't a `WithNew` type.
+fn well_known_from_text(name: &str, ty: &Type, owner: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    // Statement parsing the scalar field `field` of `message`
+    let scalar = |field: &str, scalar: &str| {
+        let ident = format_ident!("{}", field);
+        let parse = scalar_from_text(scalar, owner);
+        (field.to_string(), quote! {
+            <#owner>::text_colon(tokens, true)?;
+            message.#ident = #parse?;
+        })
+    };
+    let module = well_known_module(ty);
+    let fields = match name {
+        "Timestamp" | "Duration" => vec![scalar("seconds", "int64"), scalar("nanos", "int32")],
+        "FieldMask" => {
+            let paths = quote! {
+                <#owner>::text_colon(tokens, true)?;
+                message.paths.extend(<#owner>::text_values(tokens, <#owner>::text_string)?);
+            };
+            vec![("paths".to_string(), paths)]
+        }
+        "Any" => vec![scalar("type_url", "string"), scalar("value", "bytes")],
+        "Struct" | "Value" | "ListValue" => {
+            let struct_loop = text_fields_parse(
+                "google.protobuf.Struct",
+                vec![(
+                    "fields",
+                    quote! {
+                        <#owner>::text_colon(tokens, false)?;
+                        for (key, value) in <#owner>::text_values(tokens, parse_entry)? {
+                            message.fields.insert(key, value);
+                        }
+                    },
+                )],
+                owner,
+            );
+            let entry_loop = text_fields_parse(
+                "google.protobuf.Struct.FieldsEntry",
+                vec![
+                    ("key", quote! {
+                        <#owner>::text_colon(tokens, true)?;
+                        key = <#owner>::text_string(tokens)?;
+                    }),
+                    ("value", quote! {
+                        <#owner>::text_colon(tokens, false)?;
+                        value = parse_value(tokens)?;
+                    }),
+                ],
+                owner,
+            );
+            let kinds = [
+                ("null_value", quote! { NullValue(<#owner>::text_enum(tokens, |name| (name == "NULL_VALUE").then_some(0))?) }),
+                ("number_value", quote! { NumberValue(<#owner>::text_number(tokens)?) }),
+                ("string_value", quote! { StringValue(<#owner>::text_string(tokens)?) }),
+                ("bool_value", quote! { BoolValue(<#owner>::text_bool(tokens)?) }),
+                ("struct_value", quote! { StructValue(parse_struct(tokens)?) }),
+                ("list_value", quote! { ListValue(parse_list(tokens)?) }),
+            ];
+            let value_loop = text_fields_parse(
+                "google.protobuf.Value",
+                kinds
+                    .into_iter()
+                    .map(|(field, kind)| {
+                        let required = !matches!(field, "struct_value" | "list_value");
+                        (field, quote! {
+                            <#owner>::text_colon(tokens, #required)?;
+                            message.kind = Some(#module::value::Kind::#kind);
+                        })
+                    })
+                    .collect(),
+                owner,
+            );
+            let list_loop = text_fields_parse(
+                "google.protobuf.ListValue",
+                vec![(
+                    "values",
+                    quote! {
+                        <#owner>::text_colon(tokens, false)?;
+                        message.values.extend(<#owner>::text_values(tokens, parse_value)?);
+                    },
+                )],
+                owner,
+            );
+            let tokens_type = text_tokens_type();
+            let parse = match name {
+                "Struct" => quote! { parse_struct },
+                "Value" => quote! { parse_value },
+                _ => quote! { parse_list },
+            };
+            return quote! {{
+                fn parse_struct(tokens: &mut #tokens_type) -> pyo3::PyResult<#module::Struct> {
+                    let end = <#owner>::text_open(tokens)?;
+                    let mut message = #module::Struct::default();
+                    #struct_loop
+                    Ok(message)
+                }
+                fn parse_entry(tokens: &mut #tokens_type) -> pyo3::PyResult<(String, #module::Value)> {
+                    let end = <#owner>::text_open(tokens)?;
+                    let (mut key, mut value) = Default::default();
+                    #entry_loop
+                    Ok((key, value))
+                }
+                fn parse_value(tokens: &mut #tokens_type) -> pyo3::PyResult<#module::Value> {
+                    let end = <#owner>::text_open(tokens)?;
+                    let mut message = #module::Value::default();
+                    #value_loop
+                    Ok(message)
+                }
+                fn parse_list(tokens: &mut #tokens_type) -> pyo3::PyResult<#module::ListValue> {
+                    let end = <#owner>::text_open(tokens)?;
+                    let mut message = #module::ListValue::default();
+                    #list_loop
+                    Ok(message)
+                }
+                #parse(tokens)
+            }};
+        }
+        // Wrappers and `Empty`
+        _ => wrapped_scalar(name).map(|wrapped| scalar("value", wrapped)).into_iter().collect(),
+    };
+    let full_name = format!("google.protobuf.{}", name);
+    let fields = text_fields_parse(&full_name, fields.iter().map(|(field, parse)| (field.as_str(), parse.clone())).collect(), owner);
+    quote! {
+        <#owner>::text_open(tokens).and_then(|end| {
+            let mut message = <#ty as ::std::default::Default>::default();
+            #fields
+            Ok(message)
+        })
+    }
+}
+
+// `write_text()` writing a message in protobuf text format, with its fields in tag order like python protobuf,
+// and `merge_text()` parsing it. Returns the helper impl and the methods going into the `#[pymethods]` impl.
+fn text_methods(
+    name: &syn::Ident,
+    fields: &Fields,
+    options: &WithNewOptions,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    use syn::ext::IdentExt;
+    let (parser, helpers) = options.text_parser(name);
+    let mut writes = Vec::new();
+    let mut parses = Vec::new();
+    let mut oneofs = Vec::new();
+    for field in fields.iter() {
+        let field_name = field.ident.as_ref().unwrap();
+        let proto_name = field_name.unraw().to_string();
+        let attrs = prost_attrs(&field.attrs);
+        let is = |label: &str| attrs.iter().any(|(key, _)| key == label);
+        let ty = &field.ty;
+
+        if is("oneof") {
+            // Written at the tag of its set variant
+            let tags = attrs.iter().find(|(key, _)| key == "tags").and_then(|(_, tags)| tags.clone());
+            for tag in tags.iter().flat_map(|tags| tags.split(',')).filter_map(|tag| tag.trim().parse::<u32>().ok()) {
+                writes.push((
+                    tag,
+                    quote! {
+                        if let Some(value) = &self.#field_name {
+                            value.write_text_field(#tag, out, indent);
+                        }
+                    },
+                ));
+            }
+            let oneof_type = optional_type(ty).unwrap_or(ty);
+            oneofs.push(quote! { <#oneof_type>::merge_text_field(&field, &mut self.#field_name, tokens)? });
+            continue;
+        }
+        let tag = match prost_tag(&field.attrs) {
+            Some(tag) => tag,
+            None => continue,
+        };
+
+        let write = if let Some((_, Some(map))) = attrs.iter().find(|(key, _)| PROST_MAPS.contains(&key.as_str())) {
+            let (key_type, value_type) = map.split_once(',').unwrap_or((map, ""));
+            let value_type = match ProtoType::from_map_value(value_type) {
+                Some(value_type) => value_type,
+                None => continue,
+            };
+            let value_colon = !matches!(value_type, ProtoType::Message);
+            let generics = generic_types(ty);
+            let element = generics.get(1).copied().unwrap_or(ty);
+            let key = text_field(&ProtoType::Scalar(key_type.trim().to_string()), ty, "key", quote! { key });
+            let value = text_field(&value_type, element, "value", quote! { value });
+            let entry = text_block(&proto_name, quote! { #key #value });
+            let key_parse = text_parse(&ProtoType::Scalar(key_type.trim().to_string()), ty, &parser);
+            let value_parse = text_parse(&value_type, element, &parser);
+            let entry_fields = text_fields_parse(
+                &format!("{}.{}Entry", name, upper_camel_case(&proto_name)),
+                vec![
+                    ("key", quote! {
+                        <#parser>::text_colon(tokens, true)?;
+                        key = #key_parse?;
+                    }),
+                    ("value", quote! {
+                        <#parser>::text_colon(tokens, #value_colon)?;
+                        value = #value_parse?;
+                    }),
+                ],
+                &parser,
+            );
+            parses.push((proto_name.clone(), quote! {
+                <#parser>::text_colon(tokens, false)?;
+                let entries = <#parser>::text_values(tokens, |tokens| {
+                    let end = <#parser>::text_open(tokens)?;
+                    let (mut key, mut value) = ::std::default::Default::default();
+                    #entry_fields
+                    Ok((key, value))
+                })?;
+                self.#field_name.extend(entries);
+            }));
+            // Entries are sorted by key like python protobuf does
+            quote! {
+                let mut entries: Vec<_> = self.#field_name.iter().collect();
+                entries.sort_by(|(key, _), (other, _)| key.cmp(other));
+                for (key, value) in entries {
+                    #entry
+                }
+            }
+        } else {
+            let proto_type = match ProtoType::from_attrs(&field.attrs) {
+                Some(proto_type) => proto_type,
+                None => continue,
+            };
+            // The `:` is optional before messages
+            let colon = !matches!(proto_type, ProtoType::Message);
+            if is("repeated") {
+                let element = generic_types(ty).first().copied().unwrap_or(ty);
+                let value = text_field(&proto_type, element, &proto_name, quote! { value });
+                let parse = text_parse(&proto_type, element, &parser);
+                parses.push((proto_name.clone(), quote! {
+                    <#parser>::text_colon(tokens, #colon)?;
+                    let values = <#parser>::text_values(tokens, |tokens| #parse)?;
+                    self.#field_name.extend(values);
+                }));
+                quote! {
+                    for value in &self.#field_name {
+                        #value
+                    }
+                }
+            } else if let Some(inner) = optional_type(ty) {
+                let element = boxed_type(inner).unwrap_or(inner);
+                let value = text_field(&proto_type, element, &proto_name, quote! { value });
+                let parse = text_parse(&proto_type, element, &parser);
+                let parsed = if boxed_type(inner).is_some() {
+                    quote! { ::std::boxed::Box::new(#parse?) }
+                } else {
+                    quote! { #parse? }
+                };
+                parses.push((proto_name.clone(), quote! {
+                    <#parser>::text_colon(tokens, #colon)?;
+                    self.#field_name = Some(#parsed);
+                }));
+                quote! {
+                    if let Some(value) = &self.#field_name {
+                        #value
+                    }
+                }
+            } else {
+                let value = text_field(&proto_type, ty, &proto_name, quote! { (&self.#field_name) });
+                let parse = text_parse(&proto_type, ty, &parser);
+                parses.push((proto_name.clone(), quote! {
+                    <#parser>::text_colon(tokens, #colon)?;
+                    self.#field_name = #parse?;
+                }));
+                quote! {
+                    if self.#field_name != <#ty as ::std::default::Default>::default() {
+                        #value
+                    }
+                }
+            }
+        };
+        writes.push((tag, write));
+    }
+    writes.sort_by_key(|(tag, _)| *tag);
+    let writes = writes.into_iter().map(|(_, write)| write);
+    let (field_names, parses): (Vec<_>, Vec<_>) = parses.into_iter().unzip();
+    let oneofs = if oneofs.is_empty() {
+        quote! {}
+    } else {
+        quote! { _ if #(#oneofs)||* => {} }
+    };
+    // Any field of a message without fields is an error
+    let separator = if field_names.is_empty() && oneofs.is_empty() {
+        quote! {}
+    } else {
+        quote! { <#parser>::text_separator(tokens); }
+    };
+    let tokens_type = text_tokens_type();
+
+    let text_impl = quote! {
+        impl #name {
+            // Writes the fields of the message in protobuf text format into `out`, indented by `indent` spaces.
+            // Without prost fields, `out` stays unused.
+            #[allow(unused_variables, clippy::ptr_arg)]
+            pub fn write_text(&self, out: &mut String, indent: usize) {
+                #(#writes)*
+            }
+
+            // Merges the fields of protobuf text format `tokens` into the message, until the token `end`
+            // closing a nested message or the end of the text.
+            pub fn merge_text(&mut self, tokens: &mut #tokens_type, end: Option<&str>) -> pyo3::PyResult<()> {
+                loop {
+                    let field = match tokens.next() {
+                        Some(token) if Some(token.as_str()) == end => return Ok(()),
+                        Some(token) => token,
+                        None => match end {
+                            Some(end) => return Err(<#parser>::text_error(format!("expected {:?} but reached the end of the text", end))),
+                            None => return Ok(()),
+                        },
+                    };
+                    match field.as_str() {
+                        #(#field_names => { #parses })*
+                        #oneofs
+                        _ => {
+                            return Err(<#parser>::text_error(format!(
+                                "Message type \"{}\" has no field named \"{}\".",
+                                stringify!(#name),
+                                field
+                            )))
+                        }
+                    }
+                    #separator
+                }
+            }
+
+            #helpers
+        }
+    };
+
+    let text_methods = quote! {
+        // Mirrors `text_format.MessageToString()` of python protobuf.
+        #[pyo3(signature = (as_one_line = false))]
+        pub fn to_text(&self, as_one_line: bool) -> String {
+            let mut out = String::new();
+            self.write_text(&mut out, 0);
+            if as_one_line {
+                // Strings are escaped, so each line is a field or a brace
+                out.lines().map(str::trim).collect::<Vec<_>>().join(" ")
+            } else {
+                out
+            }
+        }
+
+        // Mirrors `text_format.Parse()` of python protobuf, returns the parsed message.
+        #[staticmethod]
+        pub fn from_text(text: &str) -> pyo3::PyResult<Self> {
+            let mut tokens = <#parser>::text_tokens(text)?.into_iter().peekable();
+            let mut message = <Self as ::std::default::Default>::default();
+            message.merge_text(&mut tokens, None)?;
+            Ok(message)
+        }
+    };
+
+    (text_impl, text_methods)
+}
+
+// `write_text_field()` writing the variant of a prost `oneof` as field of the protobuf text format of the
+// message, if its tag is `tag`, and `merge_text_field()` parsing it.
+fn oneof_text_methods(name: &syn::Ident, data: &syn::DataEnum, options: &WithNewOptions) -> proc_macro2::TokenStream {
+    let (parser, helpers) = options.text_parser(name);
+    let mut writes = Vec::new();
+    let mut parses = Vec::new();
+    for variant in &data.variants {
+        let (proto_type, tag) = match (ProtoType::from_attrs(&variant.attrs), prost_tag(&variant.attrs)) {
+            (Some(proto_type), Some(tag)) => (proto_type, tag),
+            _ => return quote! {},
+        };
+        let variant_ident = &variant.ident;
+        let proto_name = snake_case(&variant_ident.to_string());
+        let ty = &variant.fields.iter().next().unwrap().ty;
+        let element = boxed_type(ty).unwrap_or(ty);
+        let write = text_field(&proto_type, element, &proto_name, quote! { value });
+        writes.push(quote! { Self::#variant_ident(value) if tag == #tag => { #write } });
+        let colon = !matches!(proto_type, ProtoType::Message);
+        let parse = text_parse(&proto_type, element, &parser);
+        let parsed = if boxed_type(ty).is_some() {
+            quote! { ::std::boxed::Box::new(#parse?) }
+        } else {
+            quote! { #parse? }
+        };
+        parses.push(quote! {
+            #proto_name => {
+                <#parser>::text_colon(tokens, #colon)?;
+                *value = Some(Self::#variant_ident(#parsed));
+                Ok(true)
+            }
+        });
+    }
+    let tokens_type = text_tokens_type();
+
+    quote! {
+        #[allow(unused_variables)]
+        pub fn write_text_field(&self, tag: u32, out: &mut String, indent: usize) {
+            match self {
+                #(#writes)*
+                _ => {}
+            }
+        }
+
+        // Parses the value of the text format field `field` into `value`, if it is one of the `oneof`.
+        pub fn merge_text_field(field: &str, value: &mut Option<Self>, tokens: &mut #tokens_type) -> pyo3::PyResult<bool> {
+            match field {
+                #(#parses)*
+                _ => Ok(false),
+            }
+        }
+
+        #helpers
+    }
+}
+
+// Generic type arguments of a type, e.g. `K` and `V` of `HashMap<K, V>`.
+fn generic_types(ty: &Type) -> Vec<&Type> {
+    match ty {
+        Type::Path(type_path) => match type_path.path.segments.last().map(|segment| &segment.arguments) {
+            Some(PathArguments::AngleBracketed(arguments)) => arguments
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    GenericArgument::Type(ty) => Some(ty),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+// `HasField()`, `ClearField()` and `ListFields()` of python protobuf. `Option` fields track presence, other
+// fields are listed by `ListFields()` when they differ from their default value. `oneof` groups and their
+// variants can be passed by name too.
+fn field_presence_methods(
+    name: &syn::Ident,
+    field_names: &[Option<syn::Ident>],
+    field_types: &[&Type],
+    oneof_names: &[Option<syn::Ident>],
+    oneof_types: &[&Type],
+) -> proc_macro2::TokenStream {
+    use syn::ext::IdentExt;
+    let message_name = name.to_string();
+    let mut has_field_arms = Vec::new();
+    let mut without_presence = Vec::new();
+    let mut clear_field_arms = Vec::new();
+    let mut list_fields = Vec::new();
+    for (field, ty) in field_names.iter().zip(field_types.iter()) {
+        let field = field.as_ref().unwrap();
+        let field_name = field.unraw().to_string();
+        if let Some(index) = oneof_names.iter().position(|oneof| oneof.as_ref() == Some(field)) {
+            let oneof_type = oneof_types[index];
+            has_field_arms.push(quote! { #field_name => Ok(self.#field.is_some()), });
+            clear_field_arms.push(quote! {
+                #field_name => self.#field = None,
+                variant if <#oneof_type>::PY_ONEOF_FIELDS.contains(&variant) => {
+                    if self.#field.as_ref().is_some_and(|value| value.py_oneof_field() == variant) {
+                        self.#field = None;
+                    }
+                }
+            });
+            has_field_arms.push(quote! {
+                variant if <#oneof_type>::PY_ONEOF_FIELDS.contains(&variant) => {
+                    Ok(self.#field.as_ref().is_some_and(|value| value.py_oneof_field() == variant))
+                }
+            });
+            list_fields.push(quote! {
+                if let Some(value) = &self.#field {
+                    fields.push((value.py_oneof_field(), value.py_oneof_value(py)));
+                }
+            });
+        } else if optional_type(ty).is_some() {
+            has_field_arms.push(quote! { #field_name => Ok(self.#field.is_some()), });
+            clear_field_arms.push(quote! { #field_name => self.#field = None, });
+            list_fields.push(quote! {
+                if let Some(value) = &self.#field {
+                    fields.push((#field_name, pyo3::IntoPy::into_py(value.clone(), py)));
+                }
+            });
+        } else {
+            without_presence.push(field_name.clone());
+            clear_field_arms.push(quote! { #field_name => self.#field = ::std::default::Default::default(), });
+            list_fields.push(quote! {
+                if self.#field != <#ty as ::std::default::Default>::default() {
+                    fields.push((#field_name, pyo3::IntoPy::into_py(self.#field.clone(), py)));
+                }
+            });
+        }
+    }
+    if !without_presence.is_empty() {
+        has_field_arms.push(quote! {
+            #(#without_presence)|* => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Protocol message {} has no non-repeated submessage field \"{}\" nor marker for presence",
+                #message_name, field_name
+            ))),
+        });
+    }
+
+    quote! {
+        // Whether the `Option` field, `oneof` group or variant `field_name` is set.
+        pub fn HasField(&self, field_name: &str) -> pyo3::PyResult<bool> {
+            match field_name {
+                #(#has_field_arms)*
+                _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Protocol message {} has no \"{}\" field.",
+                    #message_name, field_name
+                ))),
+            }
+        }
+
+        // Resets the field `field_name` to its default value, `None` for `Option` fields and `oneof` groups.
+        #[allow(unreachable_code)]
+        pub fn ClearField(&mut self, field_name: &str) -> pyo3::PyResult<()> {
+            match field_name {
+                #(#clear_field_arms)*
+                _ => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "Protocol message {} has no \"{}\" field.",
+                        #message_name, field_name
+                    )))
+                }
+            }
+            Ok(())
+        }
+
+        // `(name, value)` of the fields which are set, in declaration order.
+        pub fn ListFields(&self, py: pyo3::Python<'_>) -> Vec<(&'static str, pyo3::PyObject)> {
+            let mut fields = Vec::new();
+            #(#list_fields)*
+            fields
+        }
+    }
+}
+
+// Inner type of `Option<T>`.
+fn optional_type(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .filter(|segment| segment.ident == "Option")
+            .and_then(|segment| first_generic_type(&segment.arguments)),
+        _ => None,
+    }
+}
+
+// Classmethods of unit-only enums like python protobuf `EnumTypeWrapper`, e.g. `Mood.Name(1) == "HAPPY"`.
+// Prost enumerations marked `#[with_new(enumeration)]` use their protobuf names from `as_str_name()`,
+// other enums their variant names.
+// PyO3 already gives them `__repr__()` and `__eq__()`, comparing with integers too, so `hash` is the only
+// opt-in method and hashes like the integer value.
+fn unit_enum_methods(input: &DeriveInput, data: &syn::DataEnum, options: &WithNewOptions) -> proc_macro2::TokenStream {
+    let name = &input.ident;
+    let enum_name = name.to_string();
+    let variants: Vec<_> = data.variants.iter().map(|variant| &variant.ident).collect();
+    let variant_names: Vec<_> = variants
+        .iter()
+        .map(|variant| {
+            if options.enumeration {
+                quote! { Self::#variant.as_str_name() }
+            } else {
+                let variant_name = variant.to_string();
+                quote! { #variant_name }
+            }
+        })
+        .collect();
+    let hash = if options.hash.is_some() {
+        quote! {
+            pub fn __hash__(&self) -> i64 {
+                match self {
+                    #(Self::#variants => Self::#variants as i64,)*
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let stub = unit_enum_stub(&enum_name, data, options);
+    let pickling = unit_enum_pickling(&variants);
+
+    quote! {
+        impl #name {
+            // Type stub of the class, see `py_stubs()` of `with_string` modules.
+            pub const PY_STUB: &'static str = #stub;
+        }
+
+        #[pyo3::pymethods]
+        impl #name {
+            #hash
+
+            // Name of the enum value `number`, raises `ValueError` for unknown values.
+            #[classmethod]
+            pub fn Name(_cls: &pyo3::Bound<'_, pyo3::types::PyType>, number: i64) -> pyo3::PyResult<&'static str> {
+                #(
+                    if number == Self::#variants as i64 {
+                        return Ok(#variant_names);
+                    }
+                )*
+                Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Enum {} has no name defined for value {}",
+                    #enum_name, number
+                )))
+            }
+
+            // Value of the enum name `name`, raises `ValueError` for unknown names.
+            #[classmethod]
+            pub fn Value(_cls: &pyo3::Bound<'_, pyo3::types::PyType>, name: &str) -> pyo3::PyResult<i64> {
+                #(
+                    if name == #variant_names {
+                        return Ok(Self::#variants as i64);
+                    }
+                )*
+                Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Enum {} has no value defined for name {:?}",
+                    #enum_name, name
+                )))
+            }
+
+            #[classmethod]
+            pub fn keys(_cls: &pyo3::Bound<'_, pyo3::types::PyType>) -> Vec<&'static str> {
+                vec![#(#variant_names),*]
+            }
+
+            #[classmethod]
+            pub fn values(_cls: &pyo3::Bound<'_, pyo3::types::PyType>) -> Vec<i64> {
+                vec![#(Self::#variants as i64),*]
+            }
+
+            #[classmethod]
+            pub fn items(_cls: &pyo3::Bound<'_, pyo3::types::PyType>) -> Vec<(&'static str, i64)> {
+                vec![#((#variant_names, Self::#variants as i64)),*]
+            }
+
+            #pickling
+        }
+    }
+}
+
+// `__reduce__()`, `__copy__()` and `__deepcopy__()` of the unit-only enum with the variants `variants`, also
+// emitted by `with_pyclass` for enums which don't derive `WithNew`.
+fn unit_enum_pickling(variants: &[&syn::Ident]) -> proc_macro2::TokenStream {
+    let variant_idents: Vec<_> = variants.iter().map(|variant| variant.to_string()).collect();
+    quote! {
+        // Pickles the value as the class attribute of its variant, `getattr(cls, variant)`.
+        pub fn __reduce__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<(pyo3::PyObject, (pyo3::PyObject, &'static str))> {
+            use pyo3::prelude::*;
+            let variant = match self {
+                #(Self::#variants => #variant_idents,)*
+            };
+            Ok((
+                py.import_bound("builtins")?.getattr("getattr")?.unbind(),
+                (py.get_type_bound::<Self>().into_any().unbind(), variant),
+            ))
+        }
+
+        // Enum values are singletons, like members of python `enum`s.
+        pub fn __copy__(slf: pyo3::Py<Self>) -> pyo3::Py<Self> {
+            slf
+        }
+
+        pub fn __deepcopy__(slf: pyo3::Py<Self>, _memo: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::Py<Self> {
+            slf
+        }
+    }
+}
+
+// `__reduce__()`, `__getstate__()`, `__setstate__()`, `__copy__()` and `__deepcopy__()` of `with_pyclass`
+// structs which don't derive `WithNew`, pickled field-wise like `concrete` classes. They have no constructor,
+// so they are unpickled through the static method `_from_state(state)`.
+fn fieldwise_pickling(fields: &Fields) -> proc_macro2::TokenStream {
+    let members: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index::from(index)),
+        })
+        .collect();
+    let indices = 0..members.len();
+    quote! {
+        // Builds an instance from the field tuple of `__getstate__()`.
+        #[staticmethod]
+        #[allow(unused_variables)]
+        pub fn _from_state(state: &pyo3::Bound<'_, pyo3::types::PyTuple>) -> pyo3::PyResult<Self> {
+            use pyo3::prelude::*;
+            Ok(Self {
+                #(#members: state.get_item(#indices)?.extract()?),*
+            })
+        }
+
+        // Pickles the instance as the call `operator.methodcaller("_from_state", state)(cls)`.
+        pub fn __reduce__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<(pyo3::PyObject, (pyo3::PyObject,))> {
+            use pyo3::prelude::*;
+            let from_state = py.import_bound("operator")?.getattr("methodcaller")?.call1(("_from_state", self.__getstate__(py)))?;
+            Ok((from_state.unbind(), (py.get_type_bound::<Self>().into_any().unbind(),)))
+        }
+
+        #[allow(unused_variables)]
+        pub fn __getstate__(&self, py: pyo3::Python<'_>) -> pyo3::PyObject {
+            let state: Vec<pyo3::PyObject> = vec![#(pyo3::IntoPy::into_py(self.#members.clone(), py)),*];
+            pyo3::types::PyTuple::new_bound(py, state).into()
+        }
+
+        pub fn __setstate__(&mut self, state: &pyo3::Bound<'_, pyo3::types::PyTuple>) -> pyo3::PyResult<()> {
+            *self = Self::_from_state(state)?;
+            Ok(())
+        }
+
+        pub fn __copy__(&self) -> Self {
+            Self {
+                #(#members: self.#members.clone()),*
+            }
+        }
+
+        pub fn __deepcopy__(&self, _memo: &pyo3::Bound<'_, pyo3::PyAny>) -> Self {
+            self.__copy__()
+        }
+    }
+}
+
+// PyO3 can't expose enums with tuple variants like prost `oneof`s, so data-carrying enums are wrapped in
+// the class `Py{Name}` with one constructor per variant, e.g. `Kind.Word("text")`. The enum converts
+// from and into that class, so message fields holding it work with `get_all` and `set_all`.
+fn data_enum_class(name: &syn::Ident, data: &syn::DataEnum, options: &WithNewOptions) -> proc_macro2::TokenStream {
+    let class = format_ident!("Py{}", name);
+    let module = options.pyclass_module();
+    let python_name = name.to_string();
+    let mut constructors = Vec::new();
+    let mut values = Vec::new();
+    let mut states = Vec::new();
+    for variant in &data.variants {
+        let variant_ident = &variant.ident;
+        let variant_name = variant_ident.to_string();
+        let fields: Vec<_> = variant.fields.iter().collect();
+        let arguments: Vec<_> = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match &field.ident {
+                Some(ident) => ident.clone(),
+                None if fields.len() == 1 => format_ident!("value"),
+                None => format_ident!("value_{}", index),
+            })
+            .collect();
+        // Boxed fields, e.g. recursive messages, are passed unboxed
+        let argument_types: Vec<_> = fields.iter().map(|field| boxed_type(&field.ty).unwrap_or(&field.ty)).collect();
+        let field_values: Vec<_> = fields
+            .iter()
+            .zip(arguments.iter())
+            .map(|(field, argument)| match boxed_type(&field.ty) {
+                Some(_) => quote! { ::std::boxed::Box::new(#argument) },
+                None => quote! { #argument },
+            })
+            .collect();
+        let unboxed_values: Vec<_> = fields
+            .iter()
+            .zip(arguments.iter())
+            .map(|(field, argument)| match boxed_type(&field.ty) {
+                Some(_) => quote! { (**#argument).clone() },
+                None => quote! { #argument.clone() },
+            })
+            .collect();
+        let (construct, pattern) = match &variant.fields {
+            Fields::Named(_) => (
+                quote! { #name::#variant_ident { #(#arguments: #field_values),* } },
+                quote! { #name::#variant_ident { #(#arguments),* } },
+            ),
+            Fields::Unnamed(_) => (
+                quote! { #name::#variant_ident(#(#field_values),*) },
+                quote! { #name::#variant_ident(#(#arguments),*) },
+            ),
+            Fields::Unit => (quote! { #name::#variant_ident }, quote! { #name::#variant_ident }),
+        };
+        let value = match unboxed_values.len() {
+            0 => quote! { py.None() },
+            1 => quote! { pyo3::IntoPy::into_py(#(#unboxed_values)*, py) },
+            _ => quote! { pyo3::IntoPy::into_py((#(#unboxed_values,)*), py) },
+        };
+
+        constructors.push(quote! {
+            #[staticmethod]
+            pub fn #variant_ident(#(#arguments: #argument_types),*) -> Self {
+                Self(#construct)
+            }
+        });
+        values.push(quote! {
+            #pattern => (#variant_name, #value)
+        });
+        states.push(quote! {
+            #pattern => (#variant_name, vec![#(pyo3::IntoPy::into_py(#unboxed_values, py)),*])
+        });
+    }
+    let oneof = oneof_methods(name, data, options);
+    let repr = if options.repr {
+        quote! {
+            // Call of the constructor of the variant, e.g. `Kind.Word('text')`.
+            pub fn __repr__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<String> {
+                use pyo3::prelude::*;
+                let (variant, fields) = self.variant_and_fields(py);
+                let fields = fields
+                    .iter()
+                    .map(|field| Ok(field.bind(py).repr()?.to_string()))
+                    .collect::<pyo3::PyResult<Vec<_>>>()?;
+                Ok(format!("{}.{}({})", #python_name, variant, fields.join(", ")))
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let comparisons = options.comparison_methods(quote! { .0 });
+    let stub = data_enum_stub(&python_name, data, options);
+    // Hashable classes are `frozen`, so they can't be changed in place by `__setstate__()`
+    let (frozen, setstate) = if options.hash.is_some() {
+        (quote! { , frozen }, quote! {})
+    } else {
+        (
+            quote! {},
+            quote! {
+                pub fn __setstate__(&mut self, state: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<()> {
+                    use pyo3::prelude::*;
+                    let (variant, fields): (String, pyo3::Bound<'_, pyo3::types::PyTuple>) = state.extract()?;
+                    let value = state.py().get_type_bound::<Self>().getattr(variant.as_str())?.call1(fields)?;
+                    *self = value.extract()?;
+                    Ok(())
+                }
+            },
+        )
+    };
+
+    quote! {
+        #[pyo3::pyclass(name = #python_name #module #frozen)]
+        #[derive(Clone)]
+        pub struct #class(pub #name);
+
+        #[pyo3::pymethods]
+        #[allow(non_snake_case)]
+        impl #class {
+            #(#constructors)*
+
+            // Name of the variant.
+            #[getter]
+            fn variant(&self, py: pyo3::Python<'_>) -> &'static str {
+                self.variant_and_value(py).0
+            }
+
+            // Data of the variant, a tuple if it has several fields and `None` if it has none.
+            #[getter]
+            fn value(&self, py: pyo3::Python<'_>) -> pyo3::PyObject {
+                self.variant_and_value(py).1
+            }
+
+            // Pickles the value as the call of the constructor of its variant, `operator.methodcaller(variant, *fields)(cls)`.
+            pub fn __reduce__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<(pyo3::PyObject, (pyo3::PyObject,))> {
+                use pyo3::prelude::*;
+                let (variant, fields) = self.variant_and_fields(py);
+                let mut args = vec![pyo3::IntoPy::into_py(variant, py)];
+                args.extend(fields);
+                let caller = py
+                    .import_bound("operator")?
+                    .getattr("methodcaller")?
+                    .call1(pyo3::types::PyTuple::new_bound(py, args))?;
+                Ok((caller.unbind(), (py.get_type_bound::<Self>().into_any().unbind(),)))
+            }
+
+            // `(variant, fields)` of the value.
+            pub fn __getstate__(&self, py: pyo3::Python<'_>) -> (&'static str, pyo3::PyObject) {
+                let (variant, fields) = self.variant_and_fields(py);
+                (variant, pyo3::types::PyTuple::new_bound(py, fields).into())
+            }
+
+            #setstate
+
+            pub fn __copy__(&self) -> Self {
+                self.clone()
+            }
+
+            pub fn __deepcopy__(&self, _memo: &pyo3::Bound<'_, pyo3::PyAny>) -> Self {
+                self.clone()
+            }
+
+            #repr
+
+            #comparisons
+        }
+
+        impl #class {
+            // Type stub of the class, see `py_stubs()` of `with_string` modules.
+            pub const PY_STUB: &'static str = #stub;
+
+            #[allow(unused_variables)]
+            fn variant_and_value(&self, py: pyo3::Python<'_>) -> (&'static str, pyo3::PyObject) {
+                match &self.0 {
+                    #(#values,)*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn variant_and_fields(&self, py: pyo3::Python<'_>) -> (&'static str, Vec<pyo3::PyObject>) {
+                match &self.0 {
+                    #(#states,)*
+                }
+            }
+        }
+
+        impl ::std::convert::From<#name> for #class {
+            fn from(other: #name) -> Self {
+                Self(other)
+            }
+        }
+
+        impl ::std::convert::From<#class> for #name {
+            fn from(other: #class) -> Self {
+                other.0
+            }
+        }
+
+        impl pyo3::IntoPy<pyo3::PyObject> for #name {
+            fn into_py(self, py: pyo3::Python<'_>) -> pyo3::PyObject {
+                pyo3::IntoPy::into_py(#class(self), py)
+            }
+        }
+
+        impl<'py> pyo3::FromPyObject<'py> for #name {
+            fn extract_bound(ob: &pyo3::Bound<'py, pyo3::PyAny>) -> pyo3::PyResult<Self> {
+                use pyo3::prelude::*;
+                Ok(ob.downcast::<#class>()?.borrow().0.clone())
+            }
+        }
+
+        #oneof
+    }
+}
+
+// Enums whose variants all hold a single value, like prost `oneof`s, are accessed by messages through
+// the snake case names of their variants, which are the fields of the `oneof` in python protobuf.
+fn oneof_methods(name: &syn::Ident, data: &syn::DataEnum, options: &WithNewOptions) -> proc_macro2::TokenStream {
+    let is_oneof = data
+        .variants
+        .iter()
+        .all(|variant| matches!(&variant.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1));
+    if !is_oneof {
+        return quote! {};
+    }
+    let variants: Vec<_> = data.variants.iter().map(|variant| &variant.ident).collect();
+    let fields: Vec<_> = variants.iter().map(|variant| snake_case(&variant.to_string())).collect();
+    let field_types: Vec<_> = data
+        .variants
+        .iter()
+        .map(|variant| {
+            let ty = &variant.fields.iter().next().unwrap().ty;
+            boxed_type(ty).unwrap_or(ty)
+        })
+        .collect();
+    let (values, from_values): (Vec<_>, Vec<_>) = data
+        .variants
+        .iter()
+        .map(|variant| match boxed_type(&variant.fields.iter().next().unwrap().ty) {
+            Some(_) => (quote! { (**value).clone() }, quote! { ::std::boxed::Box::new(value.extract()?) }),
+            None => (quote! { value.clone() }, quote! { value.extract()? }),
+        })
+        .unzip();
+
+    // Variants holding the same message merge, other ones are replaced
+    let json = oneof_json_methods(data);
+    let text = oneof_text_methods(name, data, options);
+    let merges = data
+        .variants
+        .iter()
+        .filter(|variant| prost_keys(&variant.attrs).iter().any(|key| key == "message"))
+        .map(|variant| {
+            let variant = &variant.ident;
+            let merge = merge_message(quote! { value }, quote! { other });
+            quote! { (Self::#variant(value), Self::#variant(other)) => #merge, }
+        });
+
+    quote! {
+        impl #name {
+            pub fn merge_from(&mut self, other: &Self) {
+                match (self, other) {
+                    #(#merges)*
+                    (value, other) => *value = other.clone(),
+                }
+            }
+
+            #json
+
+            #text
+
+            pub const PY_ONEOF_FIELDS: &'static [&'static str] = &[#(#fields),*];
+
+            // Field name of the variant.
+            pub fn py_oneof_field(&self) -> &'static str {
+                match self {
+                    #(Self::#variants(_) => #fields,)*
+                }
+            }
+
+            pub fn py_oneof_value(&self, py: pyo3::Python<'_>) -> pyo3::PyObject {
+                match self {
+                    #(Self::#variants(value) => pyo3::IntoPy::into_py(#values, py),)*
+                }
+            }
+
+            // Default value of the variant `field`, returned when another variant is set.
+            pub fn py_oneof_default(py: pyo3::Python<'_>, field: &str) -> pyo3::PyObject {
+                match field {
+                    #(#fields => pyo3::IntoPy::into_py(<#field_types as ::std::default::Default>::default(), py),)*
+                    _ => py.None(),
+                }
+            }
+
+            // Variant `field` holding `value`.
+            pub fn py_oneof_from(field: &str, value: &pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<Self> {
+                use pyo3::prelude::*;
+                match field {
+                    #(#fields => Ok(Self::#variants(#from_values)),)*
+                    _ => Err(pyo3::exceptions::PyAttributeError::new_err(format!(
+                        "{} has no field {:?}",
+                        stringify!(#name),
+                        field
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+// Inner type of `Box<T>`.
+fn boxed_type(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .filter(|segment| segment.ident == "Box")
+            .and_then(|segment| first_generic_type(&segment.arguments)),
+        _ => None,
+    }
+}
+
+// Generic arguments of a concrete instantiation like `Foo<i64>` of the generic struct `name`.
+fn concrete_arguments(name: &syn::Ident, concrete: &Type) -> syn::Result<Vec<proc_macro2::TokenStream>> {
+    if let Type::Path(type_path) = concrete {
+        if let Some(segment) = type_path.path.segments.last() {
+            if segment.ident == *name {
+                if let PathArguments::AngleBracketed(arguments) = &segment.arguments {
+                    return Ok(arguments
+                        .args
+                        .iter()
+                        .filter(|arg| !matches!(arg, GenericArgument::Lifetime(_)))
+                        .map(|arg| quote! { #arg })
+                        .collect());
+                }
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(
+        concrete,
+        format!("expected a concrete instantiation of `{}`, e.g. `{}<i64>`", name, name),
+    ))
+}
+
+// Replaces the generic parameters in `tokens` by their concrete arguments, e.g. `Vec<T>` by `Vec<i64>`.
+fn substitute_generics(
+    tokens: proc_macro2::TokenStream,
+    substitutions: &[(String, proc_macro2::TokenStream)],
+) -> proc_macro2::TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            proc_macro2::TokenTree::Ident(ident) => {
+                match substitutions.iter().find(|(param, _)| ident == param) {
+                    Some((_, arg)) => arg.clone(),
+                    None => quote! { #ident },
+                }
+            }
+            proc_macro2::TokenTree::Group(group) => {
+                let mut substituted =
+                    proc_macro2::Group::new(group.delimiter(), substitute_generics(group.stream(), substitutions));
+                substituted.set_span(group.span());
+                quote! { #substituted }
+            }
+            token => quote! { #token },
+        })
+        .collect()
+}
+
+// Python type of the Rust type `ty` as PyO3 converts it, for type stubs, e.g. `dict[str, int]` for
+// `HashMap<String, i64>`. Other types are named after their last path segment, like their Python classes.
+fn py_type(ty: &Type) -> String {
+    use syn::ext::IdentExt;
+    let any = || "typing.Any".to_string();
+    match ty {
+        Type::Reference(reference) => py_type(&reference.elem),
+        Type::Paren(paren) => py_type(&paren.elem),
+        Type::Group(group) => py_type(&group.elem),
+        Type::Slice(slice) => format!("list[{}]", py_type(&slice.elem)),
+        Type::Array(array) => format!("list[{}]", py_type(&array.elem)),
+        Type::Tuple(tuple) if tuple.elems.is_empty() => "None".to_string(),
+        Type::Tuple(tuple) => format!("tuple[{}]", tuple.elems.iter().map(py_type).collect::<Vec<_>>().join(", ")),
+        Type::Path(type_path) => {
+            let segment = match type_path.path.segments.last() {
+                Some(segment) => segment,
+                None => return any(),
+            };
+            let generics = generic_types(ty);
+            let argument = |index: usize| generics.get(index).map(|ty| py_type(ty)).unwrap_or_else(any);
+            match segment.ident.unraw().to_string().as_str() {
+                "bool" => "bool".to_string(),
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => {
+                    "int".to_string()
+                }
+                "f32" | "f64" => "float".to_string(),
+                "String" | "str" | "char" => "str".to_string(),
+                "Option" => format!("{} | None", argument(0)),
+                "Box" | "Rc" | "Arc" => argument(0),
+                "Vec" | "VecDeque" => format!("list[{}]", argument(0)),
+                "HashMap" | "BTreeMap" => format!("dict[{}, {}]", argument(0), argument(1)),
+                "HashSet" | "BTreeSet" => format!("set[{}]", argument(0)),
+                "PyObject" | "Py" | "Bound" | "PyAny" => any(),
+                name => name.to_string(),
+            }
+        }
+        _ => any(),
+    }
+}
+
+// Type stub of the class `python_name` with the lines `body`, which are indented into it.
+fn class_stub(python_name: &str, body: Vec<String>) -> String {
+    let mut stub = format!("class {}:\n", python_name);
+    if body.is_empty() {
+        stub.push_str("    ...\n");
+    }
+    for line in body {
+        stub.push_str(&format!("    {}\n", line));
+    }
+    stub
+}
+
+// `name: type` lines of the attributes `with_pyclass` exposes for the named fields `fields`.
+fn attribute_stubs(fields: &Fields) -> Vec<String> {
+    use syn::ext::IdentExt;
+    fields
+        .iter()
+        .filter_map(|field| Some(format!("{}: {}", field.ident.as_ref()?.unraw(), py_type(&field.ty))))
+        .collect()
+}
+
+// `__init__()` of the constructor generated by `WithNew`, with the arguments of `combined_signatures`:
+// the required fields first, then the `Option` fields defaulting to `None`. `extra` are trailing arguments.
+fn constructor_stub(required: &[(String, String)], optional: &[(String, String)], extra: &str) -> String {
+    let arguments: Vec<_> = ::std::iter::once("self".to_string())
+        .chain(required.iter().map(|(name, ty)| format!("{}: {}", name, ty)))
+        .chain(optional.iter().map(|(name, ty)| format!("{}: {} = None", name, ty)))
+        .chain((!extra.is_empty()).then(|| extra.to_string()))
+        .collect();
+    format!("def __init__({}) -> None: ...", arguments.join(", "))
+}
+
+// Stubs of the opt-in `__repr__()`, `__eq__()`, `__ne__()` and `__hash__()`.
+fn dunder_stubs(options: &WithNewOptions) -> Vec<String> {
+    let mut stubs = Vec::new();
+    if options.repr {
+        stubs.push("def __repr__(self) -> str: ...".to_string());
+    }
+    if options.eq {
+        stubs.push("def __eq__(self, other: object) -> bool: ...".to_string());
+        stubs.push("def __ne__(self, other: object) -> bool: ...".to_string());
+    }
+    if options.hash.is_some() {
+        stubs.push("def __hash__(self) -> int: ...".to_string());
+    }
+    stubs
+}
+
+// Type stub of a message class, its fields, constructor and the methods `WithNew` generates.
+fn message_stub(
+    name: &str,
+    fields: &Fields,
+    constructor: String,
+    has_oneofs: bool,
+    options: &WithNewOptions,
+) -> String {
+    let mut body = attribute_stubs(fields);
+    body.push(constructor);
+    if has_oneofs {
+        body.push("def WhichOneof(self, oneof_group: str) -> str | None: ...".to_string());
+        // The variants of `oneof`s are looked up by `__getattr__()`
+        body.push("def __getattr__(self, name: str) -> typing.Any: ...".to_string());
+    }
+    body.extend(
+        [
+            "def HasField(self, field_name: str) -> bool: ...",
+            "def ClearField(self, field_name: str) -> None: ...",
+            "def ListFields(self) -> list[tuple[str, typing.Any]]: ...",
+            "def CopyFrom(self, other: {name}) -> None: ...",
+            "def MergeFrom(self, other: {name}) -> None: ...",
+            "def ParseFromString(self, bytes_string: typing_extensions.Buffer) -> int: ...",
+            "def MergeFromString(self, bytes_string: typing_extensions.Buffer) -> int: ...",
+            "@staticmethod",
+            "def FromString(bytes_string: typing_extensions.Buffer) -> {name}: ...",
+            "def ParseDelimited(self, bytes_string: typing_extensions.Buffer) -> int: ...",
+            "def SerializeDelimited(self) -> bytes: ...",
+            "def to_json(self, indent: int | None = 2, preserving_proto_field_name: bool = False, \
+             including_default_value_fields: bool = False) -> str: ...",
+            "@staticmethod",
+            "def from_json(json: str) -> {name}: ...",
+            "def to_dict(self, preserving_proto_field_name: bool = False, \
+             including_default_value_fields: bool = False) -> dict[str, typing.Any]: ...",
+            "@staticmethod",
+            "def from_dict(d: dict[str, typing.Any]) -> {name}: ...",
+            "def to_text(self, as_one_line: bool = False) -> str: ...",
+            "@staticmethod",
+            "def from_text(text: str) -> {name}: ...",
+        ]
+        .iter()
+        .map(|line| line.replace("{name}", name)),
+    );
+    if options.str {
+        body.push("def __str__(self) -> str: ...".to_string());
+    }
+    body.extend(dunder_stubs(options));
+    body.extend(
+        [
+            "def ByteSize(self) -> int: ...",
+            "def SerializeToString(self) -> bytes: ...",
+            "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+            "def __getstate__(self) -> bytes: ...",
+            "def __setstate__(self, state: typing_extensions.Buffer) -> None: ...",
+            "def __copy__(self) -> {name}: ...",
+            "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+        ]
+        .iter()
+        .map(|line| line.replace("{name}", name)),
+    );
+    class_stub(name, body)
+}
+
+// Type stub of the class wrapping a concrete instantiation of a generic struct, with the field types
+// `field_types` substituted.
+fn concrete_stub(
+    python_name: &str,
+    field_names: &[String],
+    field_types: &[String],
+    constructor: String,
+    options: &WithNewOptions,
+) -> String {
+    let mut body: Vec<_> = field_names.iter().zip(field_types).map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+    body.push(constructor);
+    body.extend(
+        [
+            "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+            "def __getstate__(self) -> tuple[typing.Any, ...]: ...",
+            "def __setstate__(self, state: tuple[typing.Any, ...]) -> None: ...",
+            "def __copy__(self) -> {name}: ...",
+            "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+        ]
+        .iter()
+        .map(|line| line.replace("{name}", python_name)),
+    );
+    body.extend(dunder_stubs(options));
+    class_stub(python_name, body)
+}
+
+// Type stub of the class of a data-carrying enum, with one static constructor per variant.
+fn data_enum_stub(python_name: &str, data: &syn::DataEnum, options: &WithNewOptions) -> String {
+    let mut body = Vec::new();
+    for variant in &data.variants {
+        let fields: Vec<_> = variant.fields.iter().collect();
+        let arguments: Vec<_> = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| {
+                let argument = match &field.ident {
+                    Some(ident) => ident.to_string(),
+                    None if fields.len() == 1 => "value".to_string(),
+                    None => format!("value_{}", index),
+                };
+                format!("{}: {}", argument, py_type(&field.ty))
+            })
+            .collect();
+        body.push("@staticmethod".to_string());
+        body.push(format!("def {}({}) -> {}: ...", variant.ident, arguments.join(", "), python_name));
+    }
+    body.extend(
+        [
+            "@property",
+            "def variant(self) -> str: ...",
+            "@property",
+            "def value(self) -> typing.Any: ...",
+            "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+            "def __getstate__(self) -> tuple[str, tuple[typing.Any, ...]]: ...",
+            "def __setstate__(self, state: tuple[str, tuple[typing.Any, ...]]) -> None: ...",
+            "def __copy__(self) -> {name}: ...",
+            "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+        ]
+        .iter()
+        // Hashable classes are frozen and have no `__setstate__()`
+        .filter(|line| options.hash.is_none() || !line.contains("__setstate__"))
+        .map(|line| line.replace("{name}", python_name)),
+    );
+    body.extend(dunder_stubs(options));
+    class_stub(python_name, body)
+}
+
+// Type stub of a unit-only enum, whose variants PyO3 exposes as class attributes.
+fn unit_enum_stub(name: &str, data: &syn::DataEnum, options: &WithNewOptions) -> String {
+    let mut body: Vec<_> =
+        data.variants.iter().map(|variant| format!("{}: typing.ClassVar[{}]", variant.ident, name)).collect();
+    body.extend(
+        [
+            "def __int__(self) -> int: ...",
+            "def __repr__(self) -> str: ...",
+            "def __eq__(self, other: object) -> bool: ...",
+            "@classmethod",
+            "def Name(cls, number: int) -> str: ...",
+            "@classmethod",
+            "def Value(cls, name: str) -> int: ...",
+            "@classmethod",
+            "def keys(cls) -> list[str]: ...",
+            "@classmethod",
+            "def values(cls) -> list[int]: ...",
+            "@classmethod",
+            "def items(cls) -> list[tuple[str, int]]: ...",
+            "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+            "def __copy__(self) -> {name}: ...",
+            "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+        ]
+        .iter()
+        .map(|line| line.replace("{name}", name)),
+    );
+    if options.hash.is_some() {
+        body.push("def __hash__(self) -> int: ...".to_string());
+    }
+    class_stub(name, body)
+}
+
+// Whether an item derives `WithNew`, e.g. `#[derive(pyo3_macro::WithNew)]`, which records the type stub of its
+// class then.
+fn derives_with_new(attrs: &[Attribute]) -> bool {
+    attrs.iter().filter(|attr| attr.path.is_ident("derive")).any(|attr| match attr.parse_meta() {
+        Ok(syn::Meta::List(list)) => list.nested.iter().any(|nested| {
+            matches!(nested, syn::NestedMeta::Meta(syn::Meta::Path(path))
+                if path.segments.last().is_some_and(|segment| segment.ident == "WithNew"))
+        }),
+        _ => false,
+    })
+}
+
+// Paths of the `PY_STUB` constants of the classes `WithNew` and `with_pyclass` generate for the items of a
+// module, e.g. `self::msg::PyKind::PY_STUB`, including the ones of nested inline modules.
+fn module_stub_paths(items: &[Item], module: proc_macro2::TokenStream) -> Vec<proc_macro2::TokenStream> {
+    let is_with_pyclass = |attrs: &[Attribute]| {
+        attrs.iter().any(|attr| attr.path.segments.last().is_some_and(|segment| segment.ident == "with_pyclass"))
+    };
+    let mut paths = Vec::new();
+    for item in items {
+        match item {
+            Item::Struct(item_struct) if derives_with_new(&item_struct.attrs) => {
+                let name = &item_struct.ident;
+                if item_struct.generics.params.is_empty() {
+                    paths.push(quote! { #module::#name::PY_STUB });
+                } else if let Ok(options) = WithNewOptions::from_attrs(&item_struct.attrs) {
+                    for (_, python_name) in &options.concrete {
+                        let class = format_ident!("Py{}", python_name.value());
+                        paths.push(quote! { #module::#class::PY_STUB });
+                    }
+                }
+            }
+            Item::Enum(item_enum) if derives_with_new(&item_enum.attrs) => {
+                let name = &item_enum.ident;
+                if item_enum.variants.iter().all(|variant| matches!(variant.fields, Fields::Unit)) {
+                    paths.push(quote! { #module::#name::PY_STUB });
+                } else {
+                    let class = format_ident!("Py{}", name);
+                    paths.push(quote! { #module::#class::PY_STUB });
+                }
+            }
+            Item::Struct(item_struct) if is_with_pyclass(&item_struct.attrs) => {
+                let name = &item_struct.ident;
+                paths.push(quote! { #module::#name::PY_STUB });
+            }
+            Item::Enum(item_enum) if is_with_pyclass(&item_enum.attrs) => {
+                let name = &item_enum.ident;
+                paths.push(quote! { #module::#name::PY_STUB });
+            }
+            Item::Mod(item_mod) => {
+                if let Some((_, items)) = &item_mod.content {
+                    let name = &item_mod.ident;
+                    paths.extend(module_stub_paths(items, quote! { #module::#name }));
+                }
+            }
+            _ => {}
+        }
+    }
+    paths
+}
+
+// `py_stubs()` and `write_py_stubs()` of a module, rendering the type stubs of its classes `stub_paths`
+// and the ones of the `with_string` plumbing as the contents of a `.pyi` file.
+fn module_stubs(stub_paths: &[proc_macro2::TokenStream]) -> proc_macro2::TokenStream {
+    let plumbing = concat!(
+        "class DelimitedIterator:\n",
+        "    def __iter__(self) -> DelimitedIterator: ...\n",
+        "    def __next__(self) -> typing.Any: ...\n",
+        "\n",
+        "def iter_delimited(reader: typing.BinaryIO, cls: type) -> DelimitedIterator: ...\n",
+    );
+    quote! {
+        // Python type stubs of the classes of this module and its inline submodules, the contents of the `.pyi`
+        // file of the Python module they are added to.
+        pub fn py_stubs() -> String {
+            let mut stubs = String::from("# This file is @generated by pyo3_macro.\nimport typing\nimport typing_extensions\n");
+            for stub in [#(#stub_paths,)* #plumbing] {
+                stubs.push_str("\n\n");
+                stubs.push_str(stub);
+            }
+            stubs
+        }
+
+        // Writes `py_stubs()` into the file `path`, e.g. from a binary or test run as part of the build, since
+        // build scripts run before the crate is compiled.
+        pub fn write_py_stubs(path: impl AsRef<::std::path::Path>) -> ::std::io::Result<()> {
+            ::std::fs::write(path, py_stubs())
+        }
+    }
+}
+
+#[proc_macro_attribute]
+pub fn with_pyclass(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as syn::AttributeArgs);
+    let input = parse_macro_input!(item as Item);
+
+    // `module = "..."` is passed through to `#[pyclass]`, `pickle` imports classes from their module
+    let mut module = quote! {};
+    for arg in args {
+        match arg {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(name_value)) if name_value.path.is_ident("module") => {
+                let value = name_value.lit;
+                module = quote! { , module = #value };
+            }
+            arg => {
+                return syn::Error::new_spanned(arg, "unknown `with_pyclass` option, expected `module = \"...\"`")
+                    .to_compile_error()
+                    .into()
+            }
+        }
+    }
+
+    // Reconstruct the struct or enum definition block. Types deriving `WithNew` record their type stub and get
+    // pickling support there, the other ones get both here: a stub listing their attributes, or variants for
+    // enums, and `__reduce__()`, `__copy__()` and `__deepcopy__()` in a `#[pymethods]` block of their own.
+    let output = match input {
+        Item::Struct(item_struct) => {
+            let methods = (!derives_with_new(&item_struct.attrs)).then(|| {
+                let name = &item_struct.ident;
+                let (impl_generics, ty_generics, where_clause) = item_struct.generics.split_for_impl();
+                let mut body = attribute_stubs(&item_struct.fields);
+                body.extend(
+                    [
+                        "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+                        "def __getstate__(self) -> tuple[typing.Any, ...]: ...",
+                        "def __setstate__(self, state: tuple[typing.Any, ...]) -> None: ...",
+                        "def __copy__(self) -> {name}: ...",
+                        "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+                    ]
+                    .iter()
+                    .map(|line| line.replace("{name}", &name.to_string())),
+                );
+                let stub = class_stub(&name.to_string(), body);
+                let pickling = fieldwise_pickling(&item_struct.fields);
+                quote! {
+                    impl #impl_generics #name #ty_generics #where_clause {
+                        // Type stub of the class, see `py_stubs()` of `with_string` modules.
+                        pub const PY_STUB: &'static str = #stub;
+                    }
+
+                    #[pyo3::pymethods]
+                    impl #impl_generics #name #ty_generics #where_clause {
+                        #pickling
+                    }
+                }
+            });
+            quote! {
+                use pyo3::prelude::*;
+                #[pyclass(subclass, dict, get_all, set_all #module)]
+                #item_struct
+
+                #methods
+            }
+        }
+        Item::Enum(item_enum) => {
+            let methods = (!derives_with_new(&item_enum.attrs)).then(|| {
+                let name = &item_enum.ident;
+                let mut body: Vec<_> =
+                    item_enum.variants.iter().map(|variant| format!("{}: typing.ClassVar[{}]", variant.ident, name)).collect();
+                // PyO3 exposes data-carrying enums differently, only unit-only enums are pickled by variant
+                let unit_only = item_enum.variants.iter().all(|variant| matches!(variant.fields, Fields::Unit));
+                let pickling = unit_only.then(|| {
+                    body.extend(
+                        [
+                            "def __reduce__(self) -> tuple[typing.Any, ...]: ...",
+                            "def __copy__(self) -> {name}: ...",
+                            "def __deepcopy__(self, memo: typing.Any) -> {name}: ...",
+                        ]
+                        .iter()
+                        .map(|line| line.replace("{name}", &name.to_string())),
+                    );
+                    let variants: Vec<_> = item_enum.variants.iter().map(|variant| &variant.ident).collect();
+                    let pickling = unit_enum_pickling(&variants);
+                    quote! {
+                        #[pyo3::pymethods]
+                        impl #name {
+                            #pickling
+                        }
+                    }
+                });
+                let stub = class_stub(&name.to_string(), body);
+                quote! {
+                    impl #name {
+                        // Type stub of the class, see `py_stubs()` of `with_string` modules.
+                        pub const PY_STUB: &'static str = #stub;
+                    }
+
+                    #pickling
+                }
+            });
+            quote! {
+                use pyo3::prelude::*;
+                #[pyclass(get_all, set_all #module)]
+                #item_enum
+
+                #methods
+            }
+        }
+        _ => {
+            return syn::Error::new_spanned(
+                input,
+                "with_pyclass can only be used with structs or enums",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    output.into()
+}
+
+
+// Injects `MessageEncodeError`/`MessageDecodeError`, their conversions into `PyErr`, the blanket
+// `ProtobufEncoder`/`ProtobufDecoder` traits and the text format parser of the `WithNew` types into a
+// generated proto module.
+// Only inline modules are supported, e.g. `include!` the prost-build output into `mod x { ... }`.
+#[proc_macro_attribute]
+pub fn with_string(_: TokenStream, input: TokenStream) -> TokenStream {
+    // Parse the input tokens into a syntax tree
+    let mut input = parse_macro_input!(input as Item);
+    // Type stubs of the classes of the module, out-of-line modules are rejected by `with_plumbing` since
+    // their items aren't part of the input
+    let stub_paths = match &mut input {
+        Item::Mod(ItemMod { content: Some((_, items)), .. }) => {
+            use_text_parser(items, quote! { ProtobufTextParser });
+            module_stub_paths(items, quote! { self })
+        }
+        _ => Vec::new(),
+    };
+    let plumbing = protobuf_plumbing();
+    let stubs = module_stubs(&stub_paths);
+    with_plumbing(input, quote! { #plumbing #stubs }, "with_string")
+}
+
+// Points the `WithNew` types of `items` and of their inline submodules at the `ProtobufTextParser` of the
+// `with_string` module, found at `parser` from `items`, unless they set a `text_parser` themselves.
+fn use_text_parser(items: &mut [Item], parser: proc_macro2::TokenStream) {
+    for item in items {
+        let attrs = match item {
+            Item::Struct(item_struct) => &mut item_struct.attrs,
+            Item::Enum(item_enum) => &mut item_enum.attrs,
+            Item::Mod(ItemMod { content: Some((_, items)), .. }) => {
+                use_text_parser(items, quote! { super::#parser });
+                continue;
+            }
+            _ => continue,
+        };
+        let has_text_parser = WithNewOptions::from_attrs(attrs).map_or(true, |options| options.text_parser.is_some());
+        if derives_with_new(attrs) && !has_text_parser {
+            attrs.push(syn::parse_quote! { #[with_new(text_parser = #parser)] });
+        }
+    }
+}
+
+// Injects `plumbing` items into a module for the attribute macro `macro_name`.
+fn with_plumbing(input: Item, plumbing: proc_macro2::TokenStream, macro_name: &str) -> TokenStream {
+    // Check if the input is a module
+    if let Item::Mod(mut item_mod) = input {
+        let items = match &mut item_mod.content {
+            Some((_, items)) => items,
+            None => {
+                // The items of an out-of-line module aren't part of the macro input, and items placed next
+                // to the declaration would land in the parent module
+                return syn::Error::new_spanned(
+                    &item_mod,
+                    format!("{} can only be used with inline modules `mod x {{ ... }}`, not `mod x;`", macro_name),
+                )
+                .to_compile_error()
+                .into();
+            }
+        };
+        // Prepend the plumbing to the module items, so that inner attributes like
+        // `#![allow(...)]` of generated modules stay in place
+        let plumbing: syn::File = syn::parse2(plumbing).expect("plumbing is valid Rust");
+        items.splice(0..0, plumbing.items);
+        quote! { #item_mod }.into()
+    } else {
+        syn::Error::new_spanned(input, format!("{} can only be used with modules", macro_name))
+            .to_compile_error()
+            .into()
+    }
+}
+
+// Error types and traits `with_string` injects for encoding and decoding protobuf messages from Python.
+fn protobuf_plumbing() -> proc_macro2::TokenStream {
+    let text_parse_helpers = text_parse_helpers();
+    quote! {
+        // An error indicates that failing at serializing object to bytes string, like `SerializeToString()` for python protos.
+        #[derive(Debug)]
+        pub struct MessageEncodeError(pub prost::EncodeError);
+        // An error indicates that failing at deserializing object from bytes string, like `ParseFromString()` for python protos.
+        #[derive(Debug)]
+        pub struct MessageDecodeError(pub prost::DecodeError);
+
+        impl ::std::fmt::Display for MessageEncodeError {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "failed to serialize message: {}", self.0)
+            }
+        }
+
+        impl ::std::fmt::Display for MessageDecodeError {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "failed to parse message: {}", self.0)
+            }
+        }
+
+        impl ::std::convert::From<MessageEncodeError> for pyo3::PyErr {
+            fn from(err: MessageEncodeError) -> pyo3::PyErr {
+                pyo3::exceptions::PyOSError::new_err(err.to_string())
+            }
+        }
+
+        impl ::std::convert::From<MessageDecodeError> for pyo3::PyErr {
+            fn from(err: MessageDecodeError) -> pyo3::PyErr {
+                pyo3::exceptions::PyOSError::new_err(err.to_string())
+            }
+        }
+
+        impl ::std::convert::From<prost::EncodeError> for MessageEncodeError {
+            fn from(other: prost::EncodeError) -> Self {
+                Self(other)
+            }
+        }
+
+        impl ::std::convert::From<prost::DecodeError> for MessageDecodeError {
+            fn from(other: prost::DecodeError) -> Self {
+                Self(other)
+            }
+        }
+
+        pub trait ProtobufDecoder<T> where T: prost::Message + Default {
+            fn decode_proto(&self, bytes_obj: &pyo3::types::PyBytes) -> Result<T, MessageDecodeError>;
+        }
+
+        pub trait ProtobufEncoder<T> where T: prost::Message + Default {
+            fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError>;
+        }
+
+        impl<T> ProtobufDecoder<T> for T where T: prost::Message + Default {
+            fn decode_proto(&self, bytes_obj: &pyo3::types::PyBytes) -> Result<T, MessageDecodeError> {
+                let bytes = bytes_obj.as_bytes();
+                let de = prost::Message::decode(bytes);
+                Ok(de?)
+            }
+        }
+
+        impl<T> ProtobufEncoder<T> for T where T: prost::Message + Default {
+            fn encode_proto(&self, res: T) -> Result<Vec<u8>, MessageEncodeError> {
+                let mut buf = Vec::with_capacity(res.encoded_len());
+                res.encode(&mut buf)?;
+                Ok(buf)
+            }
+        }
+
+        // Iterates over the length-delimited messages of the class `cls`, e.g. written by `SerializeDelimited()`,
+        // read from the binary file-like `reader`. Each message is yielded as soon as its bytes are read, so it
+        // works on pipes and sockets which are kept open.
+        #[pyo3::pyfunction]
+        pub fn iter_delimited(reader: &pyo3::Bound<'_, pyo3::PyAny>, cls: pyo3::PyObject) -> pyo3::PyResult<DelimitedIterator> {
+            Ok(DelimitedIterator {
+                reader: reader.clone().unbind(),
+                read1: reader.hasattr("read1")?,
+                cls,
+                buffer: Vec::new(),
+                eof: false,
+            })
+        }
+
+        #[pyo3::pyclass]
+        pub struct DelimitedIterator {
+            reader: pyo3::PyObject,
+            // Whether `reader` has `read1()`, returning what's available instead of waiting for a full chunk
+            read1: bool,
+            cls: pyo3::PyObject,
+            // Bytes read from `reader` but not decoded yet
+            buffer: Vec<u8>,
+            eof: bool,
+        }
+
+        impl DelimitedIterator {
+            const CHUNK_SIZE: usize = 64 * 1024;
+
+            // Reads from `reader` until `buffer` holds `len` bytes, returns whether it does. Stops early at the end
+            // of the stream, or when a non-blocking `reader` returns `None` since no bytes are available.
+            fn fill(&mut self, py: pyo3::Python<'_>, len: usize) -> pyo3::PyResult<bool> {
+                use pyo3::prelude::*;
+                while self.buffer.len() < len && !self.eof {
+                    // Without `read1()`, only the missing bytes are asked for, since `read(n)` may wait for all of them
+                    let chunk = if self.read1 {
+                        self.reader.call_method1(py, "read1", (Self::CHUNK_SIZE,))?
+                    } else {
+                        self.reader.call_method1(py, "read", (len - self.buffer.len(),))?
+                    };
+                    let chunk = chunk.bind(py);
+                    if chunk.is_none() {
+                        return Ok(false);
+                    }
+                    let start = self.buffer.len();
+                    match chunk.downcast::<pyo3::types::PyBytes>() {
+                        Ok(bytes) => self.buffer.extend_from_slice(bytes.as_bytes()),
+                        Err(_) => self.buffer.extend(chunk.downcast::<pyo3::types::PyByteArray>()?.to_vec()),
+                    }
+                    if self.buffer.len() == start {
+                        self.eof = true;
+                    }
+                }
+                Ok(self.buffer.len() >= len)
+            }
+
+            // Ends the iteration when the next message isn't fully read, which is an error at the end of `reader`.
+            fn incomplete(&self) -> pyo3::PyResult<Option<pyo3::PyObject>> {
+                if self.eof && !self.buffer.is_empty() {
+                    return Err(pyo3::exceptions::PyEOFError::new_err("truncated length-delimited message"));
+                }
+                Ok(None)
+            }
+        }
+
+        #[pyo3::pymethods]
+        impl DelimitedIterator {
+            fn __iter__(slf: pyo3::PyRef<'_, Self>) -> pyo3::PyRef<'_, Self> {
+                slf
+            }
+
+            // Stops at the end of `reader`, and also when a non-blocking `reader` has no bytes available. The bytes
+            // read so far are kept then, so iterating again resumes.
+            fn __next__(&mut self, py: pyo3::Python<'_>) -> pyo3::PyResult<Option<pyo3::PyObject>> {
+                // The length delimiter is a varint of at most 10 bytes, read byte by byte so that no bytes past the
+                // message are waited for
+                let mut start = 0;
+                loop {
+                    if !self.fill(py, start + 1)? {
+                        return self.incomplete();
+                    }
+                    start += 1;
+                    if self.buffer[start - 1] & 0x80 == 0 || start == 10 {
+                        break;
+                    }
+                }
+                let len = prost::decode_length_delimiter(&self.buffer[..start]).map_err(MessageDecodeError::from)?;
+                if !self.fill(py, start + len)? {
+                    return self.incomplete();
+                }
+                let message = pyo3::types::PyBytes::new_bound(py, &self.buffer[start..start + len]);
+                self.buffer.drain(..start + len);
+                self.cls.call_method1(py, "FromString", (message,)).map(Some)
+            }
+        }
+
+        // Helpers parsing protobuf text format, shared by the `from_text()` of the `WithNew` types of the module
+        // and its inline submodules.
+        pub struct ProtobufTextParser;
+
+        impl ProtobufTextParser {
+            #text_parse_helpers
+        }
+    }
+}
+
+// Injects the `RpcError` exception raised for failed RPCs, like `grpc.RpcError`, with one subclass per
+// status code, e.g. `NotFoundError`, into a module. `StatusError` converts `tonic::Status` into them, pass
+// it to `#[with_grpc_client(status_error = path::to::StatusError)]` and `with_grpc_server` to use them,
+// they don't pick it up on their own. Register the classes in your Python module with `add_status_errors(m)?`.
+#[proc_macro_attribute]
+pub fn with_grpc_status(_: TokenStream, input: TokenStream) -> TokenStream {
+    // Parse the input tokens into a syntax tree
+    let input = parse_macro_input!(input as Item);
+    with_plumbing(input, grpc_status_plumbing(), "with_grpc_status")
+}
+
+// Variants of `tonic::Code`.
+const STATUS_CODES: [&str; 17] = [
+    "Ok",
+    "Cancelled",
+    "Unknown",
+    "InvalidArgument",
+    "DeadlineExceeded",
+    "NotFound",
+    "AlreadyExists",
+    "PermissionDenied",
+    "ResourceExhausted",
+    "FailedPrecondition",
+    "Aborted",
+    "OutOfRange",
+    "Unimplemented",
+    "Internal",
+    "Unavailable",
+    "DataLoss",
+    "Unauthenticated",
+];
+
+// Exception classes and conversions `with_grpc_status` injects for raising `tonic::Status` in Python.
+fn grpc_status_plumbing() -> proc_macro2::TokenStream {
+    let codes: Vec<_> = STATUS_CODES.iter().map(|code| format_ident!("{}", code)).collect();
+    let code_names: Vec<_> = STATUS_CODES.iter().map(|code| snake_case(code).to_uppercase()).collect();
+    let code_values = 0..STATUS_CODES.len() as isize;
+    // `Ok` isn't an error, so it is raised as plain `RpcError`
+    let error_codes = &codes[1..];
+    let errors: Vec<_> = error_codes.iter().map(|code| format_ident!("{}Error", code)).collect();
+    let error_names: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+
+    quote! {
+        // Status code of a failed RPC, like `grpc.StatusCode`.
+        #[pyo3::pyclass(name = "StatusCode")]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum StatusCode {
+            #(
+                #[pyo3(name = #code_names)]
+                #codes = #code_values,
+            )*
+        }
+
+        impl ::std::convert::From<tonic::Code> for StatusCode {
+            fn from(code: tonic::Code) -> Self {
+                match code {
+                    #(tonic::Code::#codes => Self::#codes,)*
+                }
+            }
+        }
+
+        impl ::std::convert::From<StatusCode> for tonic::Code {
+            fn from(code: StatusCode) -> Self {
+                match code {
+                    #(StatusCode::#codes => Self::#codes,)*
+                }
+            }
+        }
+
+        // Base of the exceptions raised for failed RPCs, like `grpc.RpcError`.
+        #[pyo3::pyclass(name = "RpcError", extends = pyo3::exceptions::PyException, subclass)]
+        pub struct RpcError {
+            code: StatusCode,
+            details: String,
+            // Binary values of `-bin` keys are kept as raw bytes
+            metadata: Vec<(String, Vec<u8>)>,
+        }
+
+        impl RpcError {
+            fn with_code(code: StatusCode, details: String) -> Self {
+                Self {
+                    code,
+                    details,
+                    metadata: Vec::new(),
+                }
+            }
+        }
+
+        #[pyo3::pymethods]
+        impl RpcError {
+            #[new]
+            #[pyo3(signature = (code, details = String::new()))]
+            fn new(code: StatusCode, details: String) -> Self {
+                Self::with_code(code, details)
+            }
+
+            fn code(&self) -> StatusCode {
+                self.code
+            }
+
+            fn details(&self) -> String {
+                self.details.clone()
+            }
+
+            // `(key, value)` pairs like `grpc.Call.trailing_metadata()`, values of `-bin` keys are `bytes`.
+            fn trailing_metadata(&self, py: pyo3::Python<'_>) -> Vec<(String, pyo3::PyObject)> {
+                use pyo3::IntoPy;
+                self.metadata
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = if key.ends_with("-bin") {
+                            pyo3::types::PyBytes::new_bound(py, value).into_py(py)
+                        } else {
+                            String::from_utf8_lossy(value).into_py(py)
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect()
+            }
+
+            fn __str__(&self) -> String {
+                format!("{:?}: {}", tonic::Code::from(self.code), self.details)
+            }
+        }
+
+        #(
+            #[pyo3::pyclass(name = #error_names, extends = RpcError)]
+            pub struct #errors;
+
+            #[pyo3::pymethods]
+            impl #errors {
+                #[new]
+                #[pyo3(signature = (details = String::new()))]
+                fn new(details: String) -> (Self, RpcError) {
+                    (Self, RpcError::with_code(StatusCode::#error_codes, details))
+                }
+            }
+        )*
+
+        // A failed RPC, raised in Python as the `RpcError` subclass of its status code.
+        #[derive(Debug)]
+        pub struct StatusError(pub tonic::Status);
+
+        impl StatusError {
+            // `tonic::Status` of a Python exception raised as `RpcError`, e.g. by a servicer.
+            pub fn status_of(py: pyo3::Python<'_>, err: &pyo3::PyErr) -> Option<tonic::Status> {
+                use pyo3::prelude::*;
+                let value = err.value_bound(py);
+                let error = value.downcast::<RpcError>().ok()?.borrow();
+                Some(tonic::Status::new(error.code.into(), error.details.clone()))
+            }
+        }
+
+        impl ::std::fmt::Display for StatusError {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "{:?}: {}", self.0.code(), self.0.message())
+            }
+        }
+
+        impl ::std::convert::From<tonic::Status> for StatusError {
+            fn from(other: tonic::Status) -> Self {
+                Self(other)
+            }
+        }
+
+        impl ::std::convert::From<StatusError> for pyo3::PyErr {
+            fn from(err: StatusError) -> pyo3::PyErr {
+                let status = err.0;
+                let metadata = status
+                    .metadata()
+                    .iter()
+                    .map(|entry| match entry {
+                        tonic::metadata::KeyAndValueRef::Ascii(key, value) => {
+                            (key.to_string(), value.as_bytes().to_vec())
+                        }
+                        tonic::metadata::KeyAndValueRef::Binary(key, value) => {
+                            (key.to_string(), value.to_bytes().map(|bytes| bytes.to_vec()).unwrap_or_default())
+                        }
+                    })
+                    .collect();
+                let error = pyo3::PyClassInitializer::from(RpcError {
+                    code: status.code().into(),
+                    details: status.message().to_string(),
+                    metadata,
+                });
+                pyo3::Python::with_gil(|py| {
+                    let value = match status.code() {
+                        #(tonic::Code::#error_codes => pyo3::Py::new(py, error.add_subclass(#errors)).map(pyo3::Py::into_any),)*
+                        tonic::Code::Ok => pyo3::Py::new(py, error).map(pyo3::Py::into_any),
+                    };
+                    match value {
+                        Ok(value) => pyo3::PyErr::from_value_bound(value.into_bound(py)),
+                        Err(err) => err,
+                    }
+                })
+            }
+        }
+
+        // Adds `StatusCode`, `RpcError` and its subclasses to a Python module.
+        pub fn add_status_errors(module: &pyo3::Bound<'_, pyo3::types::PyModule>) -> pyo3::PyResult<()> {
+            use pyo3::prelude::*;
+            module.add_class::<StatusCode>()?;
+            module.add_class::<RpcError>()?;
+            #(module.add_class::<#errors>()?;)*
+            Ok(())
+        }
+    }
+}
+
+// `InvalidArgument` becomes `invalid_argument`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+// Adds the function `list_all_async_methods()` to a tonic generated client module, listing the
+// `(name, input, output, client_streaming, server_streaming)` of every async RPC method, e.g.
+// `("check", "super::HealthCheckRequest", "super::HealthCheckResponse", false, false)`.
+// It is a free function since the client is generic over its transport, which callers would have to name.
+#[proc_macro_attribute]
+pub fn list_all_async_methods(_: TokenStream, input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as ItemMod);
+
+    let items = match &mut input.content {
+        Some((_, items)) => items,
+        None => {
+            return syn::Error::new_spanned(
+                &input,
+                "list_all_async_methods can only be used with inline modules",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let (struct_name, methods) = service_methods(items);
+    if struct_name.is_none() {
+        return syn::Error::new_spanned(&input, "No struct found in the module.")
+            .to_compile_error()
+            .into();
+    }
+
+    // Generate list_all_async_methods function
+    let methods_table = methods_table(&methods);
+    let list_all_async_methods_fn: Item = syn::parse_quote! {
+        pub fn list_all_async_methods() -> Vec<(&'static str, &'static str, &'static str, bool, bool)> {
+            #methods_table
+        }
+    };
+    items.push(list_all_async_methods_fn);
+
+    quote! { #input }.into()
+}
+
+// Adds a `#[pyclass]` wrapper of the client to a tonic generated client module, named like the client
+// in Python, e.g. `PyHealthClient` exposed as `HealthClient`. It connects on construction and has one
+// blocking method per RPC, driving the call on a tokio runtime owned by the wrapper.
+// Client streams are read from Python iterables of request messages, server streams are returned as
+// Python iterators over the response messages, e.g. `HealthClientWatchStream`.
+// With `#[with_grpc_client(asyncio)]` the methods return Python awaitables through `pyo3_async_runtimes` instead,
+// which needs its `tokio-runtime` feature.
+// Request and response messages have to be `#[with_pyclass]` types.
+#[proc_macro_attribute]
+pub fn with_grpc_client(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let options = parse_macro_input!(attr as GrpcOptions);
+    let mut input = parse_macro_input!(input as ItemMod);
+
+    let items = match &mut input.content {
+        Some((_, items)) => items,
+        None => {
+            return syn::Error::new_spanned(
+                &input,
+                "with_grpc_client can only be used with inline modules",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let (struct_name, methods) = service_methods(items);
+    let struct_name = match struct_name {
+        Some(struct_name) => struct_name,
+        None => {
+            return syn::Error::new_spanned(&input, "No struct found in the module.")
+                .to_compile_error()
+                .into();
+        }
+    };
+    let py_struct_name = format_ident!("Py{}", struct_name);
+    let py_class_name = struct_name.to_string();
+
+    let rpc_methods = methods.iter().filter_map(|method| client_method(&struct_name, method, &options));
+    let stream_classes = methods
+        .iter()
+        .filter(|method| method.server_streaming)
+        .map(|method| stream_class(&stream_struct_name(&struct_name, method), &method.output, &options));
+    let request_stream_helpers = request_stream_helpers(&methods, &options);
+    let methods_table = methods_table(&methods);
+
+    let wrapper: syn::File = syn::parse_quote! {
+        #[pyo3::pyclass(name = #py_class_name)]
+        pub struct #py_struct_name {
+            client: #struct_name<tonic::transport::Channel>,
+            runtime: tokio::runtime::Runtime,
+        }
+
+        // RPCs fail with `tonic::Status`, which is large but only boxed by tonic itself
+        #[pyo3::pymethods]
+        #[allow(clippy::result_large_err)]
+        impl #py_struct_name {
+            #[new]
+            pub fn new(py: pyo3::Python<'_>, endpoint: String) -> pyo3::PyResult<Self> {
+                let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+                let endpoint = tonic::transport::Endpoint::from_shared(endpoint)
+                    .map_err(|err| pyo3::exceptions::PyValueError::new_err(err.to_string()))?;
+                let channel = py
+                    .allow_threads(|| runtime.block_on(endpoint.connect()))
+                    .map_err(|err| pyo3::exceptions::PyConnectionError::new_err(err.to_string()))?;
+                Ok(Self {
+                    client: #struct_name::new(channel),
+                    runtime,
+                })
+            }
+
+            #[staticmethod]
+            pub fn list_all_async_methods() -> Vec<(&'static str, &'static str, &'static str, bool, bool)> {
+                #methods_table
+            }
+
+            #(#rpc_methods)*
+        }
+
+        #(#stream_classes)*
+
+        #request_stream_helpers
+    };
+    items.extend(wrapper.items);
+
+    quote! { #input }.into()
+}
+
+// Options of `with_grpc_client` and `with_grpc_server`, e.g. `#[with_grpc_client(asyncio)]`.
+#[derive(Default)]
+struct GrpcOptions {
+    // Whether RPC methods return Python awaitables instead of blocking until the response arrives.
+    asyncio: bool,
+    // Error type `tonic::Status` is converted into before it is raised in Python, like the `StatusError`
+    // of `with_grpc_status`. It has to implement `From<tonic::Status>` and `Into<PyErr>`.
+    status_error: Option<Path>,
+}
+
+impl syn::parse::Parse for GrpcOptions {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = Self::default();
+        while !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            match key.to_string().as_str() {
+                "asyncio" => options.asyncio = true,
+                "status_error" => {
+                    input.parse::<Token![=]>()?;
+                    options.status_error = Some(input.parse()?);
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &key,
+                        format!("unknown gRPC option `{}`", key),
+                    ))
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(options)
+    }
+}
+
+// Python method of the client wrapper calling an RPC, `None` for RPCs it can't call.
+fn client_method(
+    struct_name: &syn::Ident,
+    method: &AsyncMethod,
+    options: &GrpcOptions,
+) -> Option<proc_macro2::TokenStream> {
+    // tonic clients take exactly one request argument
+    if method.inputs.len() != 1 {
+        return None;
+    }
+    let method_name = &method.name;
+    let input_type = &method.inputs[0];
+    let status_error = status_error(options);
+
+    // Client streams are read from a Python iterable of request messages, which is fed into a request
+    // stream by a background task, see `py_request_stream`
+    let (request_arg, request_stream, request_error) = if method.client_streaming {
+        let request_stream = if options.asyncio {
+            quote! { let (request, request_error) = py_async_request_stream::<#input_type>(py, requests)?; }
+        } else {
+            quote! { let (request, request_error) = py_request_stream::<#input_type>(self.runtime.handle(), requests); }
+        };
+        let request_error = quote! {
+            if let Some(err) = request_error.lock().unwrap().take() {
+                return Err(err);
+            }
+        };
+        (quote! { requests: pyo3::PyObject }, request_stream, request_error)
+    } else {
+        (quote! { request: #input_type }, quote! {}, quote! {})
+    };
+
+    // Server streams are handed to Python as iterators over the response messages
+    let (runtime, output_type, output) = if method.server_streaming {
+        let stream_struct_name = stream_struct_name(struct_name, method);
+        let request_error = if method.client_streaming {
+            quote! { request_error }
+        } else {
+            quote! { PyRequestError::default() }
+        };
+        (
+            quote! { let runtime = self.runtime.handle().clone(); },
+            quote! { #stream_struct_name },
+            quote! { #stream_struct_name::new(response.into_inner(), runtime, #request_error) },
+        )
+    } else {
+        let output_type = &method.output;
+        (quote! {}, quote! { #output_type }, quote! { response.into_inner() })
+    };
+
+    let rpc_method = if options.asyncio {
+        quote! {
+            pub fn #method_name(&self, py: pyo3::Python<'_>, #request_arg) -> pyo3::PyResult<pyo3::PyObject> {
+                let mut client = self.client.clone();
+                #runtime
+                #request_stream
+                pyo3_async_runtimes::tokio::future_into_py(py, async move {
+                    let response = client.#method_name(request).await;
+                    #request_error
+                    let response = response.map_err(#status_error)?;
+                    Ok(#output)
+                })
+                .map(Into::into)
+            }
+        }
+    } else {
+        quote! {
+            pub fn #method_name(&self, py: pyo3::Python<'_>, #request_arg) -> pyo3::PyResult<#output_type> {
+                // tonic clients are cheap to clone, so concurrent calls don't contend for `&mut self`
+                let mut client = self.client.clone();
+                #runtime
+                #request_stream
+                let response = py.allow_threads(|| self.runtime.block_on(client.#method_name(request)));
+                #request_error
+                let response = response.map_err(#status_error)?;
+                Ok(#output)
+            }
+        }
+    };
+    Some(rpc_method)
+}
+
+// Helpers feeding Python iterables of request messages into tonic request streams, emitted into
+// client modules with streaming RPCs. The messages are read by a background task, a failure to read
+// them ends the request stream and is stored in a `PyRequestError` to be raised instead of the call result.
+fn request_stream_helpers(methods: &[AsyncMethod], options: &GrpcOptions) -> proc_macro2::TokenStream {
+    let client_streaming = methods.iter().any(|method| method.client_streaming);
+    let server_streaming = methods.iter().any(|method| method.server_streaming);
+    if !client_streaming && !server_streaming {
+        return quote! {};
+    }
+    let request_error = quote! {
+        // Error reading request messages from Python, raised in place of the call result.
+        type PyRequestError = std::sync::Arc<std::sync::Mutex<Option<pyo3::PyErr>>>;
+    };
+    if !client_streaming {
+        return request_error;
+    }
+
+    let async_request_stream = if options.asyncio {
+        quote! {
+            // Like `py_request_stream`, but also reads Python async iterables, awaiting their items
+            // on the event loop of the calling coroutine.
+            fn py_async_request_stream<T>(
+                py: pyo3::Python<'_>,
+                requests: pyo3::PyObject,
+            ) -> pyo3::PyResult<(tonic::codegen::tokio_stream::wrappers::ReceiverStream<T>, PyRequestError)>
+            where
+                T: for<'py> pyo3::FromPyObject<'py> + Send + 'static,
+            {
+                use pyo3::prelude::*;
+                let runtime = pyo3_async_runtimes::tokio::get_runtime();
+                if !requests.bind(py).hasattr("__aiter__")? {
+                    return Ok(py_request_stream(runtime.handle(), requests));
+                }
+                let locals = pyo3_async_runtimes::tokio::get_current_locals(py)?;
+                let iterator: pyo3::PyObject = requests.bind(py).call_method0("__aiter__")?.unbind();
+                let (sender, receiver) = tokio::sync::mpsc::channel(1);
+                let request_error = PyRequestError::default();
+                let producer_error = request_error.clone();
+                runtime.spawn(async move {
+                    loop {
+                        let next = pyo3::Python::with_gil(|py| {
+                            let awaitable = iterator.bind(py).call_method0("__anext__")?;
+                            pyo3_async_runtimes::into_future_with_locals(&locals, awaitable)
+                        });
+                        let item = match next {
+                            Ok(next) => next.await,
+                            Err(err) => Err(err),
+                        };
+                        let message = pyo3::Python::with_gil(|py| match item {
+                            Ok(item) => item.extract::<T>(py).map(Some),
+                            Err(err) if err.is_instance_of::<pyo3::exceptions::PyStopAsyncIteration>(py) => Ok(None),
+                            Err(err) => Err(err),
+                        });
+                        match message {
+                            Ok(Some(message)) => {
+                                // The call already ended when the receiver is gone
+                                if sender.send(message).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                *producer_error.lock().unwrap() = Some(err);
+                                break;
+                            }
+                        }
+                    }
+                });
+                Ok((tonic::codegen::tokio_stream::wrappers::ReceiverStream::new(receiver), request_error))
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        #request_error
+
+        // Feeds the messages of a Python iterable into a request stream from a blocking task on `runtime`.
+        fn py_request_stream<T>(
+            runtime: &tokio::runtime::Handle,
+            requests: pyo3::PyObject,
+        ) -> (tonic::codegen::tokio_stream::wrappers::ReceiverStream<T>, PyRequestError)
+        where
+            T: for<'py> pyo3::FromPyObject<'py> + Send + 'static,
+        {
+            use pyo3::prelude::*;
+            let (sender, receiver) = tokio::sync::mpsc::channel(1);
+            let request_error = PyRequestError::default();
+            let producer_error = request_error.clone();
+            runtime.spawn_blocking(move || {
+                let result = pyo3::Python::with_gil(|py| -> pyo3::PyResult<()> {
+                    for item in requests.bind(py).iter()? {
+                        let message = item?.extract::<T>()?;
+                        // The call already ended when the receiver is gone
+                        if py.allow_threads(|| sender.blocking_send(message)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                });
+                if let Err(err) = result {
+                    *producer_error.lock().unwrap() = Some(err);
+                }
+            });
+            (tonic::codegen::tokio_stream::wrappers::ReceiverStream::new(receiver), request_error)
+        }
+
+        #async_request_stream
+    }
+}
+
+// Rust name of the Python iterator over the messages streamed to an RPC method of a service,
+// e.g. `PyHealthClientWatchStream` exposed as `HealthClientWatchStream`.
+fn stream_struct_name(struct_name: &syn::Ident, method: &AsyncMethod) -> syn::Ident {
+    format_ident!("Py{}{}Stream", struct_name, upper_camel_case(&method.name.to_string()))
+}
+
+// `#[pyclass]` iterating the messages of a `tonic::codec::Streaming<T>`, like the responses of a
+// server streaming RPC. It is a blocking iterator, and also an async iterator with `asyncio`.
+fn stream_class(
+    stream_struct_name: &syn::Ident,
+    message_type: &proc_macro2::TokenStream,
+    options: &GrpcOptions,
+) -> proc_macro2::TokenStream {
+    let py_class_name = stream_struct_name.to_string().trim_start_matches("Py").to_string();
+    let output_type = message_type;
+    let status_error = status_error(options);
+
+    let async_iterator = if options.asyncio {
+        quote! {
+            fn __aiter__(slf: pyo3::PyRef<'_, Self>) -> pyo3::PyRef<'_, Self> {
+                slf
+            }
+
+            fn __anext__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<Option<pyo3::PyObject>> {
+                let stream = self.stream.clone();
+                let request_error = self.request_error.clone();
+                pyo3_async_runtimes::tokio::future_into_py(py, async move {
+                    match Self::next_message(stream, request_error).await? {
+                        Some(message) => Ok(message),
+                        None => Err(pyo3::exceptions::PyStopAsyncIteration::new_err(())),
+                    }
+                })
+                .map(|awaitable| Some(awaitable.into()))
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        #[pyo3::pyclass(name = #py_class_name)]
+        pub struct #stream_struct_name {
+            // Taken out while a message is awaited, so the stream doesn't need an async lock
+            stream: std::sync::Arc<std::sync::Mutex<Option<tonic::codec::Streaming<#output_type>>>>,
+            runtime: tokio::runtime::Handle,
+            // Set when reading the requests of a bidirectional stream from Python failed
+            request_error: PyRequestError,
+        }
+
+        impl #stream_struct_name {
+            fn new(
+                stream: tonic::codec::Streaming<#output_type>,
+                runtime: tokio::runtime::Handle,
+                request_error: PyRequestError,
+            ) -> Self {
+                Self {
+                    stream: std::sync::Arc::new(std::sync::Mutex::new(Some(stream))),
+                    runtime,
+                    request_error,
+                }
+            }
+
+            // Awaits the next response message, `None` once the server closed the stream.
+            async fn next_message(
+                stream: std::sync::Arc<std::sync::Mutex<Option<tonic::codec::Streaming<#output_type>>>>,
+                request_error: PyRequestError,
+            ) -> pyo3::PyResult<Option<#output_type>> {
+                let mut receiving = stream
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .ok_or_else(|| pyo3::exceptions::PyRuntimeError::new_err("stream is already being read"))?;
+                let message = receiving.message().await;
+                *stream.lock().unwrap() = Some(receiving);
+                match message {
+                    Ok(Some(message)) => Ok(Some(message)),
+                    message => {
+                        if let Some(err) = request_error.lock().unwrap().take() {
+                            return Err(err);
+                        }
+                        message.map_err(#status_error)
+                    }
+                }
+            }
+        }
+
+        #[pyo3::pymethods]
+        impl #stream_struct_name {
+            fn __iter__(slf: pyo3::PyRef<'_, Self>) -> pyo3::PyRef<'_, Self> {
+                slf
+            }
+
+            fn __next__(&self, py: pyo3::Python<'_>) -> pyo3::PyResult<Option<#output_type>> {
+                let stream = self.stream.clone();
+                let request_error = self.request_error.clone();
+                py.allow_threads(|| self.runtime.block_on(Self::next_message(stream, request_error)))
+            }
+
+            #async_iterator
+        }
+    }
+}
+
+// Closure mapping `tonic::Status` of a failed RPC into `PyErr`.
+// Without a configured error type, it is raised as python `RuntimeError`.
+fn status_error(options: &GrpcOptions) -> proc_macro2::TokenStream {
+    match &options.status_error {
+        Some(path) => quote! {
+            |status: tonic::Status| pyo3::PyErr::from(<#path>::from(status))
+        },
+        None => quote! {
+            |status: tonic::Status| pyo3::exceptions::PyRuntimeError::new_err(status.to_string())
+        },
+    }
+}
+
+// `field_name` becomes `fieldName`, the JSON name of protobuf fields.
+fn lower_camel_case(name: &str) -> String {
+    let camel = upper_camel_case(name);
+    let mut chars = camel.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// `server_streaming` becomes `ServerStreaming`.
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// Adds an implementation of the service trait calling into a Python servicer object to a tonic
+// generated server module, e.g. `PyHealthServicer` for `Health`, and a `#[pyclass]` serving it from
+// Python, e.g. `PyHealthServer` exposed as `HealthServer`. Every RPC calls the servicer method of the
+// same name with the request message, a Python exception fails the RPC with a matching `tonic::Status`.
+// Client streams are passed as iterators over the request messages, e.g. `EchoCollectStream`, and
+// server streams are read from the iterable the servicer method returns.
+// Request and response messages have to be `#[with_pyclass]` types.
+#[proc_macro_attribute]
+pub fn with_grpc_server(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let options = parse_macro_input!(attr as GrpcOptions);
+    let mut input = parse_macro_input!(input as ItemMod);
+    if options.asyncio {
+        return syn::Error::new(proc_macro2::Span::call_site(), "with_grpc_server doesn't support `asyncio`")
+            .to_compile_error()
+            .into();
+    }
+
+    let items = match &mut input.content {
+        Some((_, items)) => items,
+        None => {
+            return syn::Error::new_spanned(
+                &input,
+                "with_grpc_server can only be used with inline modules",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let item_trait = match items.iter().find_map(|item| match item {
+        Item::Trait(item_trait) => Some(item_trait),
+        _ => None,
+    }) {
+        Some(item_trait) => item_trait,
+        None => {
+            return syn::Error::new_spanned(&input, "No trait found in the module.")
+                .to_compile_error()
+                .into();
+        }
+    };
+    let trait_name = &item_trait.ident;
+    let methods = trait_methods(item_trait);
+    let server_name = format_ident!("{}Server", trait_name);
+    let servicer_name = format_ident!("Py{}Servicer", trait_name);
+    let py_server_name = format_ident!("Py{}Server", trait_name);
+    let py_class_name = server_name.to_string();
+
+    let handlers = methods
+        .iter()
+        .filter(|method| method.inputs.len() == 1)
+        .map(|method| servicer_method(trait_name, method));
+    let stream_classes = methods
+        .iter()
+        .filter(|method| method.client_streaming)
+        .map(|method| stream_class(&stream_struct_name(trait_name, method), &method.inputs[0], &options));
+    let request_error = if methods.iter().any(|method| method.client_streaming) {
+        quote! {
+            // Error reading request messages from Python, raised in place of the call result.
+            type PyRequestError = std::sync::Arc<std::sync::Mutex<Option<pyo3::PyErr>>>;
+        }
+    } else {
+        quote! {}
+    };
+    let response_stream = if methods.iter().any(|method| method.server_streaming) {
+        quote! {
+            // Feeds the messages of a Python iterable into a response stream from a blocking task,
+            // a failure to read them ends the stream with an error status.
+            #[allow(clippy::result_large_err)]
+            fn py_response_stream<T>(
+                responses: pyo3::PyObject,
+            ) -> tonic::codegen::tokio_stream::wrappers::ReceiverStream<Result<T, tonic::Status>>
+            where
+                T: for<'py> pyo3::FromPyObject<'py> + Send + 'static,
+            {
+                use pyo3::prelude::*;
+                let (sender, receiver) = tokio::sync::mpsc::channel(1);
+                tokio::task::spawn_blocking(move || {
+                    pyo3::Python::with_gil(|py| {
+                        let responses = match responses.bind(py).iter() {
+                            Ok(responses) => responses,
+                            Err(err) => {
+                                let _ = sender.blocking_send(Err(py_err_status(py, err)));
+                                return;
+                            }
+                        };
+                        for item in responses {
+                            let message = item
+                                .and_then(|item| item.extract::<T>())
+                                .map_err(|err| py_err_status(py, err));
+                            let failed = message.is_err();
+                            // The call already ended when the receiver is gone
+                            if py.allow_threads(|| sender.blocking_send(message)).is_err() || failed {
+                                break;
+                            }
+                        }
+                    })
+                });
+                tonic::codegen::tokio_stream::wrappers::ReceiverStream::new(receiver)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Exceptions raised from a `tonic::Status` carry it back to the client
+    let status_of_py_err = match &options.status_error {
+        Some(path) => quote! {
+            if let Some(status) = <#path>::status_of(py, &err) {
+                return status;
+            }
+        },
+        None => quote! {},
+    };
+
+    let wrapper: syn::File = syn::parse_quote! {
+        pub struct #servicer_name {
+            servicer: std::sync::Arc<pyo3::PyObject>,
+        }
+
+        #[allow(clippy::result_large_err)]
+        impl #servicer_name {
+            pub fn new(servicer: pyo3::PyObject) -> Self {
+                Self {
+                    servicer: std::sync::Arc::new(servicer),
+                }
+            }
+
+            // Calls the servicer method `name` with `request` on a blocking task, converting its result with `extract`.
+            async fn call<A, T, F>(&self, name: &'static str, request: A, extract: F) -> Result<T, tonic::Status>
+            where
+                A: pyo3::IntoPy<pyo3::Py<pyo3::types::PyTuple>> + Send + 'static,
+                T: Send + 'static,
+                F: for<'py> FnOnce(pyo3::Bound<'py, pyo3::PyAny>) -> pyo3::PyResult<T> + Send + 'static,
+            {
+                use pyo3::prelude::*;
+                let servicer = self.servicer.clone();
+                tokio::task::spawn_blocking(move || {
+                    pyo3::Python::with_gil(|py| {
+                        let servicer = servicer.bind(py);
+                        if !servicer.hasattr(name).map_err(|err| py_err_status(py, err))? {
+                            return Err(tonic::Status::unimplemented(format!("{} is not implemented", name)));
+                        }
+                        servicer
+                            .call_method1(name, request)
+                            .and_then(extract)
+                            .map_err(|err| py_err_status(py, err))
+                    })
+                })
+                .await
+                .map_err(|err| tonic::Status::internal(err.to_string()))?
+            }
+        }
+
+        #[tonic::async_trait]
+        impl #trait_name for #servicer_name {
+            #(#handlers)*
+        }
+
+        // Maps a Python exception raised by the servicer to the closest `tonic::Status`.
+        fn py_err_status(py: pyo3::Python<'_>, err: pyo3::PyErr) -> tonic::Status {
+            #status_of_py_err
+            let message = err.to_string();
+            if err.is_instance_of::<pyo3::exceptions::PyNotImplementedError>(py) {
+                tonic::Status::unimplemented(message)
+            } else if err.is_instance_of::<pyo3::exceptions::PyValueError>(py)
+                || err.is_instance_of::<pyo3::exceptions::PyTypeError>(py)
+            {
+                tonic::Status::invalid_argument(message)
+            } else if err.is_instance_of::<pyo3::exceptions::PyLookupError>(py) {
+                tonic::Status::not_found(message)
+            } else if err.is_instance_of::<pyo3::exceptions::PyPermissionError>(py) {
+                tonic::Status::permission_denied(message)
+            } else if err.is_instance_of::<pyo3::exceptions::PyTimeoutError>(py) {
+                tonic::Status::deadline_exceeded(message)
+            } else {
+                tonic::Status::unknown(message)
+            }
+        }
+
+        #[pyo3::pyclass(name = #py_class_name)]
+        pub struct #py_server_name {
+            servicer: pyo3::PyObject,
+        }
+
+        #[pyo3::pymethods]
+        impl #py_server_name {
+            #[new]
+            pub fn new(servicer: pyo3::PyObject) -> Self {
+                Self { servicer }
+            }
+
+            // Serves the servicer on `address`, e.g. `"127.0.0.1:50051"`, until a signal like
+            // `KeyboardInterrupt` is raised in Python.
+            pub fn serve(&self, py: pyo3::Python<'_>, address: String) -> pyo3::PyResult<()> {
+                let address: std::net::SocketAddr = address
+                    .parse()
+                    .map_err(|err: std::net::AddrParseError| pyo3::exceptions::PyValueError::new_err(err.to_string()))?;
+                let service = #server_name::new(#servicer_name::new(self.servicer.clone_ref(py)));
+                let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+                py.allow_threads(|| {
+                    runtime.block_on(async {
+                        let mut interrupted = Ok(());
+                        // Signals are only delivered to Python while the GIL is taken, so check them periodically
+                        let signals = async {
+                            loop {
+                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                                if let Err(err) = pyo3::Python::with_gil(|py| py.check_signals()) {
+                                    interrupted = Err(err);
+                                    break;
+                                }
+                            }
+                        };
+                        tonic::transport::Server::builder()
+                            .add_service(service)
+                            .serve_with_shutdown(address, signals)
+                            .await
+                            .map_err(|err| pyo3::exceptions::PyConnectionError::new_err(err.to_string()))?;
+                        interrupted
+                    })
+                })
+            }
+        }
+
+        #(#stream_classes)*
+
+        #request_error
+
+        #response_stream
+    };
+    items.extend(wrapper.items);
+
+    quote! { #input }.into()
+}
+
+// Implementation of a service trait method calling the Python servicer.
+fn servicer_method(trait_name: &syn::Ident, method: &AsyncMethod) -> proc_macro2::TokenStream {
+    let method_name = &method.name;
+    let py_method_name = method_name.to_string();
+    let input_type = &method.inputs[0];
+    let output_type = &method.output;
+
+    let (request_type, request) = if method.client_streaming {
+        let stream_struct_name = stream_struct_name(trait_name, method);
+        (
+            quote! { tonic::Streaming<#input_type> },
+            quote! {
+                #stream_struct_name::new(
+                    request.into_inner(),
+                    tokio::runtime::Handle::current(),
+                    PyRequestError::default(),
+                )
+            },
+        )
+    } else {
+        (quote! { #input_type }, quote! { request.into_inner() })
+    };
+
+    if method.server_streaming {
+        let stream_type = format_ident!("{}Stream", upper_camel_case(&py_method_name));
+        quote! {
+            type #stream_type = tonic::codegen::tokio_stream::wrappers::ReceiverStream<Result<#output_type, tonic::Status>>;
+
+            async fn #method_name(
+                &self,
+                request: tonic::Request<#request_type>,
+            ) -> Result<tonic::Response<Self::#stream_type>, tonic::Status> {
+                let responses = self
+                    .call(#py_method_name, (#request,), |responses| Ok(responses.unbind()))
+                    .await?;
+                Ok(tonic::Response::new(py_response_stream(responses)))
+            }
+        }
+    } else {
+        quote! {
+            async fn #method_name(
+                &self,
+                request: tonic::Request<#request_type>,
+            ) -> Result<tonic::Response<#output_type>, tonic::Status> {
+                let response = self
+                    .call(#py_method_name, (#request,), |response| pyo3::types::PyAnyMethods::extract::<#output_type>(&response))
+                    .await?;
+                Ok(tonic::Response::new(response))
+            }
+        }
+    }
+}
+
+// An async RPC method of a tonic generated service, with its message types unwrapped by `deepest_type`.
+struct AsyncMethod {
+    name: syn::Ident,
+    inputs: Vec<proc_macro2::TokenStream>,
+    output: proc_macro2::TokenStream,
+    // Whether requests are sent as a stream, like `impl tonic::IntoStreamingRequest<Message = T>`.
+    client_streaming: bool,
+    // Whether responses are received as a stream, like `tonic::Response<tonic::codec::Streaming<T>>`.
+    server_streaming: bool,
+}
+
+// Extracts the service struct name and all async methods from the items of a tonic generated module.
+fn service_methods(items: &[Item]) -> (Option<syn::Ident>, Vec<AsyncMethod>) {
+    let mut methods = Vec::new();
+    let mut struct_name = None;
+
+    for item in items {
+        if let Item::Struct(ItemStruct { ident, .. }) = item {
+            if struct_name.is_none() {
+                struct_name = Some(ident.clone());
+            }
+        }
+
+        if let Item::Impl(item_impl) = item {
+            if let Type::Path(type_path) = &*item_impl.self_ty {
+                if struct_name.is_none() {
+                    struct_name = type_path.path.segments.last().map(|segment| segment.ident.clone());
+                }
+                methods.extend(async_methods(item_impl));
+            }
+        }
+    }
+
+    (struct_name, methods)
+}
+
+// Collects name, input types and output type of the async methods taking `self` in an impl block,
+// e.g. the RPC methods of a tonic client, unwrapped to the message types by `deepest_type`.
+fn async_methods(item_impl: &ItemImpl) -> Vec<AsyncMethod> {
+    item_impl
+        .items
+        .iter()
+        .filter_map(|impl_item| match impl_item {
+            ImplItem::Method(method) => async_method(&method.sig),
+            _ => None,
+        })
+        .collect()
+}
+
+// Collects the async methods of a tonic generated service trait, like `async_methods`.
+// Server streams are returned as associated types, e.g. `Self::WatchStream`, which are resolved to
+// the message type of their `Stream<Item = Result<T, Status>>` bound.
+fn trait_methods(item_trait: &ItemTrait) -> Vec<AsyncMethod> {
+    let mut methods = Vec::new();
+    for trait_item in &item_trait.items {
+        if let TraitItem::Method(method) = trait_item {
+            if let Some(mut async_method) = async_method(&method.sig) {
+                let associated_type = item_trait.items.iter().find_map(|trait_item| match trait_item {
+                    TraitItem::Type(item_type) => {
+                        let ident = &item_type.ident;
+                        (async_method.output.to_string() == quote!(Self::#ident).to_string()).then_some(item_type)
+                    }
+                    _ => None,
+                });
+                let stream_bound = associated_type.and_then(|item_type| {
+                    item_type.bounds.iter().find_map(|bound| match bound {
+                        TypeParamBound::Trait(trait_bound) => trait_bound.path.segments.last(),
+                        _ => None,
+                    })
+                });
+                if let Some(inner_ty) = stream_bound.and_then(|segment| first_generic_type(&segment.arguments)) {
+                    async_method.output = deepest_type(inner_ty);
+                    async_method.server_streaming = true;
+                }
+                methods.push(async_method);
+            }
+        }
+    }
+    methods
+}
+
+// Name, input types and output type of an async method taking `self`, `None` for other methods.
+fn async_method(sig: &syn::Signature) -> Option<AsyncMethod> {
+    // Skip constructors like `connect()`, which are async but not RPC methods
+    if sig.asyncness.is_none() || sig.receiver().is_none() {
+        return None;
+    }
+    let mut inputs = Vec::new();
+    let mut client_streaming = false;
+
+    // Collect input types
+    for input in &sig.inputs {
+        if let FnArg::Typed(pat_type) = input {
+            client_streaming |= is_streaming(&pat_type.ty);
+            inputs.push(deepest_type(&pat_type.ty));
+        }
+    }
+
+    // Collect return type
+    let (output, server_streaming) = match &sig.output {
+        ReturnType::Default => (quote! {()}, false),
+        ReturnType::Type(_, ty) => (deepest_type(ty), is_streaming(ty)),
+    };
+
+    Some(AsyncMethod {
+        name: sig.ident.clone(),
+        inputs,
+        output,
+        client_streaming,
+        server_streaming,
+    })
+}
+
+// Builds the `vec![(name, input, output, client_streaming, server_streaming), ...]` table of `list_all_async_methods()`.
+fn methods_table(methods: &[AsyncMethod]) -> proc_macro2::TokenStream {
+    let method_names = methods.iter().map(|method| method.name.to_string());
+    let input_types = methods.iter().map(|method| {
+        let inputs: Vec<_> = method.inputs.iter().map(type_name).collect();
+        match inputs.len() {
+            1 => inputs[0].clone(),
+            _ => format!("({})", inputs.join(", ")),
+        }
+    });
+    let output_types = methods.iter().map(|method| type_name(&method.output));
+    let client_streaming = methods.iter().map(|method| method.client_streaming);
+    let server_streaming = methods.iter().map(|method| method.server_streaming);
+    quote! {
+        vec![
+            #((#method_names, #input_types, #output_types, #client_streaming, #server_streaming)),*
+        ]
+    }
+}
+
+// Unwraps the first generic argument until reaching the innermost type, e.g. the message type
+// `super::HelloReply` of `Result<tonic::Response<super::HelloReply>, tonic::Status>` or
+// `impl tonic::IntoRequest<super::HelloRequest>`.
+fn deepest_type(ty: &Type) -> proc_macro2::TokenStream {
+    match ty {
+        Type::Path(type_path) => {
+            if let Some(segment) = type_path.path.segments.last() {
+                if segment.arguments.is_empty() {
+                    return quote! {#type_path};
+                } else if let Some(inner_ty) = first_generic_type(&segment.arguments) {
+                    return deepest_type(inner_ty);
+                }
+            }
+        }
+        Type::ImplTrait(impl_trait) => {
+            for bound in &impl_trait.bounds {
+                if let TypeParamBound::Trait(trait_bound) = bound {
+                    if let Some(segment) = trait_bound.path.segments.last() {
+                        if let Some(inner_ty) = first_generic_type(&segment.arguments) {
+                            return deepest_type(inner_ty);
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    quote! {#ty}
+}
+
+// Whether a request or response type is a stream of messages, like `tonic::codec::Streaming<T>`
+// or `impl tonic::IntoStreamingRequest<Message = T>`.
+fn is_streaming(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.segments.iter().any(|segment| {
+            segment.ident == "Streaming" || first_generic_type(&segment.arguments).is_some_and(is_streaming)
+        }),
+        Type::ImplTrait(impl_trait) => impl_trait.bounds.iter().any(|bound| {
+            matches!(bound, TypeParamBound::Trait(trait_bound)
+                if trait_bound.path.segments.iter().any(|segment| segment.ident == "IntoStreamingRequest"))
+        }),
+        _ => false,
+    }
+}
+
+// First type argument of `<T, ...>` or associated type binding of `<Message = T>`.
+fn first_generic_type(arguments: &PathArguments) -> Option<&Type> {
+    if let PathArguments::AngleBracketed(args) = arguments {
+        for arg in &args.args {
+            match arg {
+                GenericArgument::Type(inner_ty) => return Some(inner_ty),
+                GenericArgument::Binding(binding) => return Some(&binding.ty),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+// Renders a type the way it reads in source, `super :: Foo` becomes `super::Foo`.
+fn type_name(ty: &proc_macro2::TokenStream) -> String {
+    ty.to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" <", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Blob {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| common::run_python(py, code, &[("Blob", common::class::<Blob>(py))]));
+}
+
+#[test]
+fn parse_from_buffers() {
+    run(r#"
+import mmap
+data = Blob("b", b"\x00" * 1000).SerializeToString()
+for source in (data, bytearray(data), memoryview(data), memoryview(bytearray(data)), memoryview(b"xx" + data)[2:]):
+    blob = Blob("", b"")
+    assert blob.ParseFromString(source) == len(data)
+    assert (blob.name, bytes(blob.data)) == ("b", b"\x00" * 1000)
+    assert Blob.FromString(source).name == "b"
+    merged = Blob("", b"")
+    assert merged.MergeFromString(source) == len(data)
+    assert merged.name == "b"
+
+with mmap.mmap(-1, len(data)) as mapped:
+    mapped.write(data)
+    assert bytes(Blob.FromString(mapped).data) == b"\x00" * 1000
+    blob = Blob("", b"")
+    assert blob.ParseFromString(mapped) == len(data)
+
+delimited = Blob("d", b"").SerializeDelimited()
+blob = Blob("", b"")
+assert blob.ParseDelimited(bytearray(delimited + b"rest")) == len(delimited)
+assert blob.name == "d"
+assert Blob.FromString(bytearray()).name == ""
+"#);
+}
+
+#[test]
+fn rejects_other_objects() {
+    run(r#"
+
+for source in ("text", 42, None):
+    try:
+        Blob.FromString(source)
+        raise AssertionError("expected TypeError")
+    except TypeError:
+        pass
+try:
+    Blob.FromString(memoryview(b"abcd")[::2])
+    raise AssertionError("expected BufferError")
+except BufferError:
+    pass
+"#);
+}
@@ -0,0 +1,21 @@
+// Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use pyo3::prelude::*;
+
+// Python class of the `#[pyclass]` `T`, to pass it to `run_python()`.
+pub fn class<T: pyo3::PyTypeInfo>(py: Python<'_>) -> PyObject {
+    py.get_type_bound::<T>().into_any().unbind()
+}
+
+// Runs the Python `code` with `globals`, printing the Python traceback when it fails.
+pub fn run_python(py: Python<'_>, code: &str, globals: &[(&str, PyObject)]) {
+    let dict = pyo3::types::PyDict::new_bound(py);
+    for (name, value) in globals {
+        dict.set_item(name, value).unwrap();
+    }
+    if let Err(err) = py.run_bound(code, Some(&dict), None) {
+        err.print(py);
+        panic!("Python code failed: {}", err);
+    }
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[pyo3_macro::with_string]
+pub mod proto {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    pub struct Record {
+        #[prost(string, tag = "1")]
+        pub text: ::prost::alloc::string::String,
+        #[prost(int64, tag = "2")]
+        pub id: i64,
+    }
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        let iter_delimited = pyo3::wrap_pyfunction_bound!(proto::iter_delimited, py).unwrap();
+        common::run_python(
+            py,
+            code,
+            &[("Record", common::class::<proto::Record>(py)), ("iter_delimited", iter_delimited.into_any().unbind())],
+        );
+    });
+}
+
+#[test]
+fn from_string() {
+    run(r#"
+r = Record.FromString(Record("a", 1).SerializeToString())
+assert (r.text, r.id) == ("a", 1)
+assert Record.FromString(b"").text == ""
+try:
+    Record.FromString(b"\x0a\x05a")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#);
+}
+
+#[test]
+fn delimited_round_trip() {
+    run(r#"
+records = [Record("a", 1), Record("", 0), Record("x" * 300, 2**40)]
+data = b"".join(r.SerializeDelimited() for r in records)
+assert Record("a", 1).SerializeDelimited() == bytes([len(Record("a", 1).SerializeToString())]) + Record("a", 1).SerializeToString()
+r = Record("old", 9)
+offset = 0
+parsed = []
+while offset < len(data):
+    offset += r.ParseDelimited(data[offset:])
+    parsed.append((r.text, r.id))
+assert offset == len(data)
+assert parsed == [(r.text, r.id) for r in records]
+try:
+    r.ParseDelimited(Record("abc", 1).SerializeDelimited()[:-1])
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#);
+}
+
+#[test]
+fn iter_delimited_over_bytes_io() {
+    run(r#"
+import io
+records = [Record(str(i) * i, i) for i in range(200)]
+stream = io.BytesIO(b"".join(r.SerializeDelimited() for r in records))
+parsed = [(r.text, r.id) for r in iter_delimited(stream, Record)]
+assert parsed == [(r.text, r.id) for r in records]
+assert list(iter_delimited(io.BytesIO(b""), Record)) == []
+try:
+    list(iter_delimited(io.BytesIO(Record("abc", 1).SerializeDelimited()[:-1]), Record))
+    raise AssertionError("expected EOFError")
+except EOFError:
+    pass
+"#);
+}
+
+#[test]
+fn iter_delimited_over_open_pipes() {
+    run(r#"
+import os, threading
+
+# Each message is yielded once read, without waiting for more bytes or the end of the pipe
+for buffering in (-1, 0):
+    read_fd, write_fd = os.pipe()
+    with os.fdopen(read_fd, "rb", buffering=buffering) as reader, os.fdopen(write_fd, "wb", buffering=0) as writer:
+        records = iter_delimited(reader, Record)
+        for i in range(3):
+            writer.write(Record("x" * 200 * i, i).SerializeDelimited())
+            parsed = []
+            thread = threading.Thread(target=lambda: parsed.append(next(records)), daemon=True)
+            thread.start()
+            thread.join(10)
+            assert [(r.text, r.id) for r in parsed] == [("x" * 200 * i, i)], (buffering, i, parsed)
+        writer.close()
+        assert list(records) == []
+"#);
+}
+
+#[test]
+fn iter_delimited_over_other_readers() {
+    run(r#"
+class Reader:
+    # Returns `bytearray`s of at most 3 bytes, and `None` like non-blocking streams without available bytes
+    def __init__(self, chunks):
+        self.chunks = chunks
+
+    def read(self, size):
+        chunk = self.chunks.pop(0) if self.chunks else b""
+        if chunk is None or isinstance(chunk, (str, int)):
+            return chunk
+        if len(chunk) > size:
+            self.chunks.insert(0, chunk[size:])
+        return bytearray(chunk[:size])
+
+data = b"".join(Record(t, i).SerializeDelimited() for t, i in (("abcdef", 1), ("g", 2)))
+chunks = [data[i:i + 3] for i in range(0, len(data), 3)]
+records = iter_delimited(Reader(chunks[:2] + [None] + chunks[2:]), Record)
+# `None` stops the iteration, iterating again resumes with the bytes read so far
+assert [(r.text, r.id) for r in records] == []
+assert [(r.text, r.id) for r in records] == [("abcdef", 1), ("g", 2)]
+for bad in (["text"], [1]):
+    try:
+        list(iter_delimited(Reader(bad), Record))
+        raise AssertionError("expected TypeError")
+    except TypeError:
+        pass
+"#);
+}
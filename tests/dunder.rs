@@ -0,0 +1,138 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+#[with_new(repr, str, eq)]
+pub struct Config {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "3")]
+    pub owner: ::core::option::Option<Owner>,
+    #[prost(string, optional, tag = "4")]
+    pub note: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(oneof = "config::Choice", tags = "5, 6")]
+    pub choice: ::core::option::Option<config::Choice>,
+}
+/// Nested message and enum types in `Config`.
+pub mod config {
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Oneof, pyo3_macro::WithNew)]
+    #[with_new(repr, eq, hash)]
+    pub enum Choice {
+        #[prost(string, tag = "5")]
+        Word(::prost::alloc::string::String),
+        #[prost(message, tag = "6")]
+        Sub(super::Owner),
+    }
+}
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message, pyo3_macro::WithNew)]
+#[with_new(repr, str, eq)]
+pub struct Owner {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub id: i64,
+}
+
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, Copy, PartialEq, pyo3_macro::WithNew)]
+#[with_new(hash)]
+pub enum Color {
+    Red = 1,
+    Green = 4,
+}
+
+#[derive(Clone, Debug, PartialEq, pyo3_macro::WithNew)]
+#[with_new(concrete(Pair<i64> = "IntPair"), repr, eq)]
+pub struct Pair<T> {
+    pub name: String,
+    pub values: Vec<T>,
+    pub first: Option<T>,
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            code,
+            &[
+                ("Config", common::class::<Config>(py)),
+                ("Owner", common::class::<Owner>(py)),
+                ("Choice", common::class::<config::PyChoice>(py)),
+                ("Color", common::class::<Color>(py)),
+                ("IntPair", common::class::<PyIntPair>(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn repr() {
+    run(r#"
+o = Owner("o", 1)
+assert repr(o) == "Owner(name='o', id=1)", repr(o)
+c = Config("c", ["x"], o, word="w")
+assert repr(c) == "Config(name='c', tags=['x'], owner=Owner(name='o', id=1), note=None, word='w')", repr(c)
+assert eval(repr(c)) == c
+# subclasses show their own name
+class Sub(Config):
+    pass
+assert repr(Sub("s", [])).startswith("Sub(name='s'")
+assert repr(IntPair("p", [1], None)) == "IntPair(name='p', values=[1], first=None)"
+assert repr(Choice.Word("w")) == "Choice.Word('w')"
+assert repr(Choice.Sub(o)) == "Choice.Sub(Owner(name='o', id=1))"
+"#);
+}
+
+#[test]
+fn str_as_text_format() {
+    run(r#"
+assert str(Owner("o", 1)) == 'name: "o"\nid: 1\n', str(Owner("o", 1))
+assert str(Owner("", 0)) == ""
+c = Config("c", ["x", "y"], Owner("o", 1), note="", word="w")
+assert str(c) == 'name: "c"\ntags: "x"\ntags: "y"\nowner {\n  name: "o"\n  id: 1\n}\nnote: ""\nword: "w"\n', str(c)
+"#);
+}
+
+#[test]
+fn eq() {
+    run(r#"
+o = Owner("o", 1)
+assert o == Owner("o", 1) and not (o != Owner("o", 1))
+assert o != Owner("o", 2) and not (o == Owner("o", 2))
+# other types compare unequal instead of raising
+assert o != "o" and not (o == 1)
+assert Config("c", [], o) == Config("c", [], Owner("o", 1)) != Config("c", [], o, word="w")
+p = IntPair("p", [1], None)
+assert p == IntPair("p", [1], None) and p != IntPair("p", [2], None)
+w = Choice.Word("w")
+assert w == Choice.Word("w") and w != Choice.Word("x") and w != Choice.Sub(o)
+"#);
+}
+
+#[test]
+fn hash() {
+    run(r#"
+import copy
+
+w = Choice.Word("w")
+assert hash(w) == hash(Choice.Word("w"))
+assert len({w, Choice.Word("w"), Choice.Sub(Owner("o", 1))}) == 2
+assert hash(Color.Green) == hash(4) and {Color.Green: 1}[Color.Green] == 1
+# hashable `Oneof` classes are frozen, copies hash alike
+assert not hasattr(w, "__setstate__")
+assert hash(copy.deepcopy(w)) == hash(w) and copy.copy(w) == w
+# messages without `hash` stay unhashable, they are mutable
+try:
+    hash(Owner("o", 1))
+except TypeError:
+    pass
+else:
+    raise AssertionError("expected TypeError")
+"#);
+}
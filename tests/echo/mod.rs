@@ -0,0 +1,608 @@
+// The `Echo` service as generated by prost-build and tonic-build, shared by the gRPC tests.
+//
+// service Echo {
+//   rpc Unary(Msg) returns (Msg);
+//   rpc Collect(stream Msg) returns (Msg);
+//   rpc Expand(Msg) returns (stream Msg);
+//   rpc Chat(stream Msg) returns (stream Msg);
+// }
+// RPCs fail with `tonic::Status`, which is large but how tonic defines them.
+#![allow(dead_code, clippy::result_large_err)]
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Msg {
+    #[prost(string, tag = "1")]
+    pub text: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub count: i64,
+}
+
+// Declares the generated client module `$name` with the attribute macros `$attr` applied, the
+// module refers to the messages as `super::Msg`.
+#[macro_export]
+macro_rules! echo_client {
+    ($(#[$attr:meta])* $name:ident) => {
+        /// Generated client implementations.
+        $(#[$attr])*
+        pub mod $name {
+            #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+            use tonic::codegen::*;
+            use tonic::codegen::http::Uri;
+            #[derive(Debug, Clone)]
+            pub struct EchoClient<T> {
+                inner: tonic::client::Grpc<T>,
+            }
+            impl EchoClient<tonic::transport::Channel> {
+                /// Attempt to create a new client by connecting to a given endpoint.
+                pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+                where
+                    D: TryInto<tonic::transport::Endpoint>,
+                    D::Error: Into<StdError>,
+                {
+                    let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+                    Ok(Self::new(conn))
+                }
+            }
+            impl<T> EchoClient<T>
+            where
+                T: tonic::client::GrpcService<tonic::body::BoxBody>,
+                T::Error: Into<StdError>,
+                T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+                <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+            {
+                pub fn new(inner: T) -> Self {
+                    let inner = tonic::client::Grpc::new(inner);
+                    Self { inner }
+                }
+                pub fn with_origin(inner: T, origin: Uri) -> Self {
+                    let inner = tonic::client::Grpc::with_origin(inner, origin);
+                    Self { inner }
+                }
+                pub fn with_interceptor<F>(
+                    inner: T,
+                    interceptor: F,
+                ) -> EchoClient<InterceptedService<T, F>>
+                where
+                    F: tonic::service::Interceptor,
+                    T::ResponseBody: Default,
+                    T: tonic::codegen::Service<
+                        http::Request<tonic::body::BoxBody>,
+                        Response = http::Response<
+                            <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                        >,
+                    >,
+                    <T as tonic::codegen::Service<
+                        http::Request<tonic::body::BoxBody>,
+                    >>::Error: Into<StdError> + Send + Sync,
+                {
+                    EchoClient::new(InterceptedService::new(inner, interceptor))
+                }
+                /// Compress requests with the given encoding.
+                ///
+                /// This requires the server to support it otherwise it might respond with an
+                /// error.
+                #[must_use]
+                pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.inner = self.inner.send_compressed(encoding);
+                    self
+                }
+                /// Enable decompressing responses.
+                #[must_use]
+                pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.inner = self.inner.accept_compressed(encoding);
+                    self
+                }
+                /// Limits the maximum size of a decoded message.
+                ///
+                /// Default: `4MB`
+                #[must_use]
+                pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+                    self.inner = self.inner.max_decoding_message_size(limit);
+                    self
+                }
+                /// Limits the maximum size of an encoded message.
+                ///
+                /// Default: `usize::MAX`
+                #[must_use]
+                pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+                    self.inner = self.inner.max_encoding_message_size(limit);
+                    self
+                }
+                pub async fn unary(
+                    &mut self,
+                    request: impl tonic::IntoRequest<super::Msg>,
+                ) -> std::result::Result<tonic::Response<super::Msg>, tonic::Status> {
+                    self.inner
+                        .ready()
+                        .await
+                        .map_err(|e| {
+                            tonic::Status::new(
+                                tonic::Code::Unknown,
+                                format!("Service was not ready: {}", e.into()),
+                            )
+                        })?;
+                    let codec = tonic::codec::ProstCodec::default();
+                    let path = http::uri::PathAndQuery::from_static("/echo.Echo/Unary");
+                    let mut req = request.into_request();
+                    req.extensions_mut().insert(GrpcMethod::new("echo.Echo", "Unary"));
+                    self.inner.unary(req, path, codec).await
+                }
+                pub async fn collect(
+                    &mut self,
+                    request: impl tonic::IntoStreamingRequest<Message = super::Msg>,
+                ) -> std::result::Result<tonic::Response<super::Msg>, tonic::Status> {
+                    self.inner
+                        .ready()
+                        .await
+                        .map_err(|e| {
+                            tonic::Status::new(
+                                tonic::Code::Unknown,
+                                format!("Service was not ready: {}", e.into()),
+                            )
+                        })?;
+                    let codec = tonic::codec::ProstCodec::default();
+                    let path = http::uri::PathAndQuery::from_static("/echo.Echo/Collect");
+                    let mut req = request.into_streaming_request();
+                    req.extensions_mut().insert(GrpcMethod::new("echo.Echo", "Collect"));
+                    self.inner.client_streaming(req, path, codec).await
+                }
+                pub async fn expand(
+                    &mut self,
+                    request: impl tonic::IntoRequest<super::Msg>,
+                ) -> std::result::Result<
+                    tonic::Response<tonic::codec::Streaming<super::Msg>>,
+                    tonic::Status,
+                > {
+                    self.inner
+                        .ready()
+                        .await
+                        .map_err(|e| {
+                            tonic::Status::new(
+                                tonic::Code::Unknown,
+                                format!("Service was not ready: {}", e.into()),
+                            )
+                        })?;
+                    let codec = tonic::codec::ProstCodec::default();
+                    let path = http::uri::PathAndQuery::from_static("/echo.Echo/Expand");
+                    let mut req = request.into_request();
+                    req.extensions_mut().insert(GrpcMethod::new("echo.Echo", "Expand"));
+                    self.inner.server_streaming(req, path, codec).await
+                }
+                pub async fn chat(
+                    &mut self,
+                    request: impl tonic::IntoStreamingRequest<Message = super::Msg>,
+                ) -> std::result::Result<
+                    tonic::Response<tonic::codec::Streaming<super::Msg>>,
+                    tonic::Status,
+                > {
+                    self.inner
+                        .ready()
+                        .await
+                        .map_err(|e| {
+                            tonic::Status::new(
+                                tonic::Code::Unknown,
+                                format!("Service was not ready: {}", e.into()),
+                            )
+                        })?;
+                    let codec = tonic::codec::ProstCodec::default();
+                    let path = http::uri::PathAndQuery::from_static("/echo.Echo/Chat");
+                    let mut req = request.into_streaming_request();
+                    req.extensions_mut().insert(GrpcMethod::new("echo.Echo", "Chat"));
+                    self.inner.streaming(req, path, codec).await
+                }
+            }
+        }
+    };
+}
+
+// Declares the generated server module `$name` with the attribute macros `$attr` applied, the
+// module refers to the messages as `super::Msg`.
+#[macro_export]
+macro_rules! echo_server {
+    ($(#[$attr:meta])* $name:ident) => {
+        /// Generated server implementations.
+        $(#[$attr])*
+        pub mod $name {
+            #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+            use tonic::codegen::*;
+            /// Generated trait containing gRPC methods that should be implemented for use with EchoServer.
+            #[async_trait]
+            pub trait Echo: Send + Sync + 'static {
+                async fn unary(
+                    &self,
+                    request: tonic::Request<super::Msg>,
+                ) -> std::result::Result<tonic::Response<super::Msg>, tonic::Status>;
+                async fn collect(
+                    &self,
+                    request: tonic::Request<tonic::Streaming<super::Msg>>,
+                ) -> std::result::Result<tonic::Response<super::Msg>, tonic::Status>;
+                /// Server streaming response type for the Expand method.
+                type ExpandStream: tonic::codegen::tokio_stream::Stream<
+                        Item = std::result::Result<super::Msg, tonic::Status>,
+                    >
+                    + Send
+                    + 'static;
+                async fn expand(
+                    &self,
+                    request: tonic::Request<super::Msg>,
+                ) -> std::result::Result<tonic::Response<Self::ExpandStream>, tonic::Status>;
+                /// Server streaming response type for the Chat method.
+                type ChatStream: tonic::codegen::tokio_stream::Stream<
+                        Item = std::result::Result<super::Msg, tonic::Status>,
+                    >
+                    + Send
+                    + 'static;
+                async fn chat(
+                    &self,
+                    request: tonic::Request<tonic::Streaming<super::Msg>>,
+                ) -> std::result::Result<tonic::Response<Self::ChatStream>, tonic::Status>;
+            }
+            #[derive(Debug)]
+            pub struct EchoServer<T: Echo> {
+                inner: _Inner<T>,
+                accept_compression_encodings: EnabledCompressionEncodings,
+                send_compression_encodings: EnabledCompressionEncodings,
+                max_decoding_message_size: Option<usize>,
+                max_encoding_message_size: Option<usize>,
+            }
+            struct _Inner<T>(Arc<T>);
+            impl<T: Echo> EchoServer<T> {
+                pub fn new(inner: T) -> Self {
+                    Self::from_arc(Arc::new(inner))
+                }
+                pub fn from_arc(inner: Arc<T>) -> Self {
+                    let inner = _Inner(inner);
+                    Self {
+                        inner,
+                        accept_compression_encodings: Default::default(),
+                        send_compression_encodings: Default::default(),
+                        max_decoding_message_size: None,
+                        max_encoding_message_size: None,
+                    }
+                }
+                pub fn with_interceptor<F>(
+                    inner: T,
+                    interceptor: F,
+                ) -> InterceptedService<Self, F>
+                where
+                    F: tonic::service::Interceptor,
+                {
+                    InterceptedService::new(Self::new(inner), interceptor)
+                }
+                /// Enable decompressing requests with the given encoding.
+                #[must_use]
+                pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.accept_compression_encodings.enable(encoding);
+                    self
+                }
+                /// Compress responses with the given encoding, if the client supports it.
+                #[must_use]
+                pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+                    self.send_compression_encodings.enable(encoding);
+                    self
+                }
+                /// Limits the maximum size of a decoded message.
+                ///
+                /// Default: `4MB`
+                #[must_use]
+                pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+                    self.max_decoding_message_size = Some(limit);
+                    self
+                }
+                /// Limits the maximum size of an encoded message.
+                ///
+                /// Default: `usize::MAX`
+                #[must_use]
+                pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+                    self.max_encoding_message_size = Some(limit);
+                    self
+                }
+            }
+            impl<T, B> tonic::codegen::Service<http::Request<B>> for EchoServer<T>
+            where
+                T: Echo,
+                B: Body + Send + 'static,
+                B::Error: Into<StdError> + Send + 'static,
+            {
+                type Response = http::Response<tonic::body::BoxBody>;
+                type Error = std::convert::Infallible;
+                type Future = BoxFuture<Self::Response, Self::Error>;
+                fn poll_ready(
+                    &mut self,
+                    _cx: &mut Context<'_>,
+                ) -> Poll<std::result::Result<(), Self::Error>> {
+                    Poll::Ready(Ok(()))
+                }
+                fn call(&mut self, req: http::Request<B>) -> Self::Future {
+                    let inner = self.inner.clone();
+                    match req.uri().path() {
+                        "/echo.Echo/Unary" => {
+                            #[allow(non_camel_case_types)]
+                            struct UnarySvc<T: Echo>(pub Arc<T>);
+                            impl<T: Echo> tonic::server::UnaryService<super::Msg>
+                            for UnarySvc<T> {
+                                type Response = super::Msg;
+                                type Future = BoxFuture<
+                                    tonic::Response<Self::Response>,
+                                    tonic::Status,
+                                >;
+                                fn call(
+                                    &mut self,
+                                    request: tonic::Request<super::Msg>,
+                                ) -> Self::Future {
+                                    let inner = Arc::clone(&self.0);
+                                    let fut = async move {
+                                        <T as Echo>::unary(&inner, request).await
+                                    };
+                                    Box::pin(fut)
+                                }
+                            }
+                            let accept_compression_encodings = self.accept_compression_encodings;
+                            let send_compression_encodings = self.send_compression_encodings;
+                            let max_decoding_message_size = self.max_decoding_message_size;
+                            let max_encoding_message_size = self.max_encoding_message_size;
+                            let inner = self.inner.clone();
+                            let fut = async move {
+                                let inner = inner.0;
+                                let method = UnarySvc(inner);
+                                let codec = tonic::codec::ProstCodec::default();
+                                let mut grpc = tonic::server::Grpc::new(codec)
+                                    .apply_compression_config(
+                                        accept_compression_encodings,
+                                        send_compression_encodings,
+                                    )
+                                    .apply_max_message_size_config(
+                                        max_decoding_message_size,
+                                        max_encoding_message_size,
+                                    );
+                                let res = grpc.unary(method, req).await;
+                                Ok(res)
+                            };
+                            Box::pin(fut)
+                        }
+                        "/echo.Echo/Collect" => {
+                            #[allow(non_camel_case_types)]
+                            struct CollectSvc<T: Echo>(pub Arc<T>);
+                            impl<T: Echo> tonic::server::ClientStreamingService<super::Msg>
+                            for CollectSvc<T> {
+                                type Response = super::Msg;
+                                type Future = BoxFuture<
+                                    tonic::Response<Self::Response>,
+                                    tonic::Status,
+                                >;
+                                fn call(
+                                    &mut self,
+                                    request: tonic::Request<tonic::Streaming<super::Msg>>,
+                                ) -> Self::Future {
+                                    let inner = Arc::clone(&self.0);
+                                    let fut = async move {
+                                        <T as Echo>::collect(&inner, request).await
+                                    };
+                                    Box::pin(fut)
+                                }
+                            }
+                            let accept_compression_encodings = self.accept_compression_encodings;
+                            let send_compression_encodings = self.send_compression_encodings;
+                            let max_decoding_message_size = self.max_decoding_message_size;
+                            let max_encoding_message_size = self.max_encoding_message_size;
+                            let inner = self.inner.clone();
+                            let fut = async move {
+                                let inner = inner.0;
+                                let method = CollectSvc(inner);
+                                let codec = tonic::codec::ProstCodec::default();
+                                let mut grpc = tonic::server::Grpc::new(codec)
+                                    .apply_compression_config(
+                                        accept_compression_encodings,
+                                        send_compression_encodings,
+                                    )
+                                    .apply_max_message_size_config(
+                                        max_decoding_message_size,
+                                        max_encoding_message_size,
+                                    );
+                                let res = grpc.client_streaming(method, req).await;
+                                Ok(res)
+                            };
+                            Box::pin(fut)
+                        }
+                        "/echo.Echo/Expand" => {
+                            #[allow(non_camel_case_types)]
+                            struct ExpandSvc<T: Echo>(pub Arc<T>);
+                            impl<T: Echo> tonic::server::ServerStreamingService<super::Msg>
+                            for ExpandSvc<T> {
+                                type Response = super::Msg;
+                                type ResponseStream = T::ExpandStream;
+                                type Future = BoxFuture<
+                                    tonic::Response<Self::ResponseStream>,
+                                    tonic::Status,
+                                >;
+                                fn call(
+                                    &mut self,
+                                    request: tonic::Request<super::Msg>,
+                                ) -> Self::Future {
+                                    let inner = Arc::clone(&self.0);
+                                    let fut = async move {
+                                        <T as Echo>::expand(&inner, request).await
+                                    };
+                                    Box::pin(fut)
+                                }
+                            }
+                            let accept_compression_encodings = self.accept_compression_encodings;
+                            let send_compression_encodings = self.send_compression_encodings;
+                            let max_decoding_message_size = self.max_decoding_message_size;
+                            let max_encoding_message_size = self.max_encoding_message_size;
+                            let inner = self.inner.clone();
+                            let fut = async move {
+                                let inner = inner.0;
+                                let method = ExpandSvc(inner);
+                                let codec = tonic::codec::ProstCodec::default();
+                                let mut grpc = tonic::server::Grpc::new(codec)
+                                    .apply_compression_config(
+                                        accept_compression_encodings,
+                                        send_compression_encodings,
+                                    )
+                                    .apply_max_message_size_config(
+                                        max_decoding_message_size,
+                                        max_encoding_message_size,
+                                    );
+                                let res = grpc.server_streaming(method, req).await;
+                                Ok(res)
+                            };
+                            Box::pin(fut)
+                        }
+                        "/echo.Echo/Chat" => {
+                            #[allow(non_camel_case_types)]
+                            struct ChatSvc<T: Echo>(pub Arc<T>);
+                            impl<T: Echo> tonic::server::StreamingService<super::Msg>
+                            for ChatSvc<T> {
+                                type Response = super::Msg;
+                                type ResponseStream = T::ChatStream;
+                                type Future = BoxFuture<
+                                    tonic::Response<Self::ResponseStream>,
+                                    tonic::Status,
+                                >;
+                                fn call(
+                                    &mut self,
+                                    request: tonic::Request<tonic::Streaming<super::Msg>>,
+                                ) -> Self::Future {
+                                    let inner = Arc::clone(&self.0);
+                                    let fut = async move {
+                                        <T as Echo>::chat(&inner, request).await
+                                    };
+                                    Box::pin(fut)
+                                }
+                            }
+                            let accept_compression_encodings = self.accept_compression_encodings;
+                            let send_compression_encodings = self.send_compression_encodings;
+                            let max_decoding_message_size = self.max_decoding_message_size;
+                            let max_encoding_message_size = self.max_encoding_message_size;
+                            let inner = self.inner.clone();
+                            let fut = async move {
+                                let inner = inner.0;
+                                let method = ChatSvc(inner);
+                                let codec = tonic::codec::ProstCodec::default();
+                                let mut grpc = tonic::server::Grpc::new(codec)
+                                    .apply_compression_config(
+                                        accept_compression_encodings,
+                                        send_compression_encodings,
+                                    )
+                                    .apply_max_message_size_config(
+                                        max_decoding_message_size,
+                                        max_encoding_message_size,
+                                    );
+                                let res = grpc.streaming(method, req).await;
+                                Ok(res)
+                            };
+                            Box::pin(fut)
+                        }
+                        _ => {
+                            Box::pin(async move {
+                                Ok(
+                                    http::Response::builder()
+                                        .status(200)
+                                        .header("grpc-status", "12")
+                                        .header("content-type", "application/grpc")
+                                        .body(empty_body())
+                                        .unwrap(),
+                                )
+                            })
+                        }
+                    }
+                }
+            }
+            impl<T: Echo> Clone for EchoServer<T> {
+                fn clone(&self) -> Self {
+                    let inner = self.inner.clone();
+                    Self {
+                        inner,
+                        accept_compression_encodings: self.accept_compression_encodings,
+                        send_compression_encodings: self.send_compression_encodings,
+                        max_decoding_message_size: self.max_decoding_message_size,
+                        max_encoding_message_size: self.max_encoding_message_size,
+                    }
+                }
+            }
+            impl<T: Echo> Clone for _Inner<T> {
+                fn clone(&self) -> Self {
+                    Self(Arc::clone(&self.0))
+                }
+            }
+            impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, "{:?}", self.0)
+                }
+            }
+            impl<T: Echo> tonic::server::NamedService for EchoServer<T> {
+                const NAME: &'static str = "echo.Echo";
+            }
+        }
+    };
+}
+
+echo_server!(echo_server);
+
+use echo_server::{Echo, EchoServer};
+use std::pin::Pin;
+use tokio_stream::StreamExt;
+
+type MsgStream = Pin<Box<dyn tokio_stream::Stream<Item = Result<Msg, tonic::Status>> + Send>>;
+
+// Echoes the requests back: `unary` appends "!", `collect` concatenates, `expand` repeats a message
+// `count` times and `chat` upper-cases every message.
+pub struct EchoService;
+
+#[tonic::async_trait]
+impl Echo for EchoService {
+    async fn unary(&self, request: tonic::Request<Msg>) -> Result<tonic::Response<Msg>, tonic::Status> {
+        let msg = request.into_inner();
+        if msg.text == "missing" {
+            return Err(tonic::Status::not_found("no such text"));
+        }
+        Ok(tonic::Response::new(Msg { text: msg.text + "!", count: msg.count + 1 }))
+    }
+
+    async fn collect(&self, request: tonic::Request<tonic::Streaming<Msg>>) -> Result<tonic::Response<Msg>, tonic::Status> {
+        let mut stream = request.into_inner();
+        let mut total = Msg { text: String::new(), count: 0 };
+        while let Some(msg) = stream.next().await {
+            let msg = msg?;
+            total.text.push_str(&msg.text);
+            total.count += msg.count;
+        }
+        Ok(tonic::Response::new(total))
+    }
+
+    type ExpandStream = MsgStream;
+
+    async fn expand(&self, request: tonic::Request<Msg>) -> Result<tonic::Response<MsgStream>, tonic::Status> {
+        let msg = request.into_inner();
+        let msgs: Vec<_> = (0..msg.count).map(|count| Ok(Msg { text: msg.text.clone(), count })).collect();
+        Ok(tonic::Response::new(Box::pin(tokio_stream::iter(msgs))))
+    }
+
+    type ChatStream = MsgStream;
+
+    async fn chat(&self, request: tonic::Request<tonic::Streaming<Msg>>) -> Result<tonic::Response<MsgStream>, tonic::Status> {
+        let stream = request.into_inner().map(|msg| msg.map(|msg| Msg { text: msg.text.to_uppercase(), count: msg.count }));
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+}
+
+// Serves `EchoService` on a free local port in the background, returns its address.
+pub fn serve() -> String {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            sender.send(listener.local_addr().unwrap()).unwrap();
+            tonic::transport::Server::builder()
+                .add_service(EchoServer::new(EchoService))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+    });
+    format!("http://{}", receiver.recv().unwrap())
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Order {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(oneof = "order::Payment", tags = "2, 3")]
+    pub payment: ::core::option::Option<order::Payment>,
+}
+/// Nested message and enum types in `Order`.
+pub mod order {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Payment {
+        #[prost(string, tag = "2")]
+        Card(::prost::alloc::string::String),
+        #[prost(int64, tag = "3")]
+        Points(i64),
+    }
+}
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, Copy, PartialEq, pyo3_macro::WithNew)]
+pub enum Color {
+    Red = 1,
+    Green = 4,
+}
+
+// A single derive, like prost-build emits with `enum_attribute("Status", "#[derive(WithNew)]")`
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration, pyo3_macro::WithNew)]
+#[with_new(enumeration)]
+#[repr(i32)]
+pub enum Status {
+    Unspecified = 0,
+    Open = 1,
+    Shipped = 2,
+}
+impl Status {
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Status::Unspecified => "STATUS_UNSPECIFIED",
+            Status::Open => "OPEN",
+            Status::Shipped => "SHIPPED",
+        }
+    }
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "STATUS_UNSPECIFIED" => Some(Self::Unspecified),
+            "OPEN" => Some(Self::Open),
+            "SHIPPED" => Some(Self::Shipped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, pyo3_macro::WithNew)]
+pub enum Shape {
+    Circle { radius: f64 },
+    Rect(i64, i64),
+    Nested(Box<Shape>),
+    Empty,
+}
+
+#[test]
+fn unit_enum_helpers() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+assert Color.Name(4) == "Green"
+assert Color.Value("Red") == 1
+assert Color.keys() == ["Red", "Green"]
+assert Color.values() == [1, 4]
+assert Color.items() == [("Red", 1), ("Green", 4)]
+for call, arg in ((Color.Name, 2), (Color.Value, "RED")):
+    try:
+        call(arg)
+        raise AssertionError("expected ValueError")
+    except ValueError:
+        pass
+"#,
+            &[("Color", common::class::<Color>(py))],
+        );
+    });
+}
+
+#[test]
+fn enumeration_names() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+assert Status.keys() == ["STATUS_UNSPECIFIED", "OPEN", "SHIPPED"]
+assert Status.Value("SHIPPED") == 2 and Status.Name(1) == "OPEN"
+assert Status.items()[2] == ("SHIPPED", 2)
+"#,
+            &[("Status", common::class::<Status>(py))],
+        );
+    });
+}
+
+#[test]
+fn data_enum_wrappers() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+card = Payment.Card("4242")
+assert (card.variant, card.value) == ("Card", "4242")
+assert (Payment.Points(7).variant, Payment.Points(7).value) == ("Points", 7)
+assert Shape.Circle(2.5).value == 2.5
+assert (Shape.Rect(2, 3).variant, Shape.Rect(2, 3).value) == ("Rect", (2, 3))
+assert Shape.Nested(Shape.Empty()).value.variant == "Empty"
+assert Shape.Empty().value is None
+assert type(card).__name__ == "Payment"
+
+order = Order("o1", card)
+assert order.payment.value == "4242"
+order.payment = Payment.Points(3)
+assert order.payment.variant == "Points"
+order.payment = None
+assert order.payment is None
+try:
+    order.payment = "4242"
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+"#,
+            &[
+                ("Payment", common::class::<order::PyPayment>(py)),
+                ("Shape", common::class::<PyShape>(py)),
+                ("Order", common::class::<Order>(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn data_enum_conversions() {
+    let shape = Shape::Rect(1, 2);
+    assert_eq!(Shape::from(PyShape::from(shape.clone())), shape);
+    Python::with_gil(|py| {
+        let object = shape.clone().into_py(py);
+        assert_eq!(object.extract::<Shape>(py).unwrap(), shape);
+        assert_eq!(object.getattr(py, "variant").unwrap().extract::<String>(py).unwrap(), "Rect");
+    });
+}
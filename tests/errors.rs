@@ -0,0 +1,77 @@
+mod common;
+
+use pyo3::prelude::*;
+
+// Error types configured with `#[with_new(decode_error = ..., encode_error = ...)]`.
+#[derive(Debug)]
+pub struct DecodeError(pub prost::DecodeError);
+#[derive(Debug)]
+pub struct EncodeError(pub prost::EncodeError);
+
+impl From<prost::DecodeError> for DecodeError {
+    fn from(err: prost::DecodeError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<prost::EncodeError> for EncodeError {
+    fn from(err: prost::EncodeError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<DecodeError> for PyErr {
+    fn from(err: DecodeError) -> Self {
+        pyo3::exceptions::PyIOError::new_err(format!("custom decode error: {}", err.0))
+    }
+}
+
+impl From<EncodeError> for PyErr {
+    fn from(err: EncodeError) -> Self {
+        pyo3::exceptions::PyIOError::new_err(format!("custom encode error: {}", err.0))
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Plain {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+#[with_new(decode_error = crate::DecodeError, encode_error = crate::EncodeError)]
+pub struct Custom {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+}
+
+#[test]
+fn decode_errors() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+# Field 1 declared as a length-delimited string of 5 bytes with only 1 byte following.
+truncated = b"\x0a\x05a"
+try:
+    Plain("").ParseFromString(truncated)
+    raise AssertionError("expected ValueError")
+except ValueError as err:
+    assert "buffer underflow" in str(err), err
+try:
+    Custom("").ParseFromString(truncated)
+    raise AssertionError("expected OSError")
+except OSError as err:
+    assert str(err).startswith("custom decode error: "), err
+c = Custom("")
+c.ParseFromString(Custom("x").SerializeToString())
+assert c.name == "x"
+"#,
+            &[("Plain", common::class::<Plain>(py)), ("Custom", common::class::<Custom>(py))],
+        );
+    });
+}
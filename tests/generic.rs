@@ -0,0 +1,54 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, pyo3_macro::WithNew)]
+#[with_new(concrete(Pair<i64> = "IntPair", Pair<String> = "StrPair"))]
+pub struct Pair<T> {
+    pub left: T,
+    pub right: T,
+    pub tags: Vec<T>,
+}
+
+#[test]
+fn concrete_classes() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+p = IntPair(1, 2, [3])
+assert (p.left, p.right, p.tags) == (1, 2, [3])
+p.right = 5
+p.tags = [4, 6]
+assert (p.right, p.tags) == (5, [4, 6])
+assert type(p).__name__ == "IntPair"
+s = StrPair("a", "b", [])
+assert (s.left, s.right, s.tags) == ("a", "b", [])
+try:
+    IntPair("a", 2, [])
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+try:
+    p.left = "a"
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+"#,
+            &[("IntPair", common::class::<PyIntPair>(py)), ("StrPair", common::class::<PyStrPair>(py))],
+        );
+    });
+}
+
+#[test]
+fn conversions() {
+    let pair = Pair { left: 1, right: 2, tags: vec![] };
+    let wrapped = PyIntPair::from(pair.clone());
+    assert_eq!(wrapped.0, pair);
+    assert_eq!(Pair::from(wrapped), pair);
+    Python::with_gil(|py| {
+        let wrapped = Py::new(py, PyIntPair::from(pair.clone())).unwrap();
+        assert_eq!(wrapped.getattr(py, "right").unwrap().extract::<i64>(py).unwrap(), 2);
+        assert_eq!(wrapped.borrow(py).0, pair);
+    });
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Messages implementing `prost::Message` by hand, to record whether the GIL is held while prost
+// encodes or decodes them.
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, Debug, Default, PartialEq, pyo3_macro::WithNew)]
+pub struct Released {
+    pub value: i64,
+}
+
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, Debug, Default, PartialEq, pyo3_macro::WithNew)]
+#[with_new(hold_gil)]
+pub struct Held {
+    pub value: i64,
+}
+
+macro_rules! recording_message {
+    ($name:ident, $encoded_with_gil:ident, $decoded_with_gil:ident) => {
+        static $encoded_with_gil: AtomicBool = AtomicBool::new(false);
+        static $decoded_with_gil: AtomicBool = AtomicBool::new(false);
+
+        impl prost::Message for $name {
+            fn encode_raw<B: prost::bytes::BufMut>(&self, buf: &mut B) {
+                $encoded_with_gil.store(gil_held(), Ordering::SeqCst);
+                if self.value != 0 {
+                    prost::encoding::int64::encode(1, &self.value, buf);
+                }
+            }
+
+            fn merge_field<B: prost::bytes::Buf>(
+                &mut self,
+                tag: u32,
+                wire_type: prost::encoding::WireType,
+                buf: &mut B,
+                ctx: prost::encoding::DecodeContext,
+            ) -> Result<(), prost::DecodeError> {
+                $decoded_with_gil.store(gil_held(), Ordering::SeqCst);
+                match tag {
+                    1 => prost::encoding::int64::merge(wire_type, &mut self.value, buf, ctx),
+                    _ => prost::encoding::skip_field(wire_type, tag, buf, ctx),
+                }
+            }
+
+            fn encoded_len(&self) -> usize {
+                if self.value != 0 {
+                    prost::encoding::int64::encoded_len(1, &self.value)
+                } else {
+                    0
+                }
+            }
+
+            fn clear(&mut self) {
+                self.value = 0;
+            }
+        }
+    };
+}
+
+recording_message!(Released, RELEASED_ENCODED_WITH_GIL, RELEASED_DECODED_WITH_GIL);
+recording_message!(Held, HELD_ENCODED_WITH_GIL, HELD_DECODED_WITH_GIL);
+
+fn gil_held() -> bool {
+    // Safety: only reads the thread state, it may be called without holding the GIL
+    unsafe { pyo3::ffi::PyGILState_Check() == 1 }
+}
+
+// Runs `code` with the classes and returns whether the GIL was held while encoding and decoding.
+fn run(code: &str, encoded_with_gil: &AtomicBool, decoded_with_gil: &AtomicBool) -> (bool, bool) {
+    encoded_with_gil.store(true, Ordering::SeqCst);
+    decoded_with_gil.store(true, Ordering::SeqCst);
+    let mut held = (false, false);
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            code,
+            &[("Released", common::class::<Released>(py)), ("Held", common::class::<Held>(py))],
+        );
+        held = (encoded_with_gil.load(Ordering::SeqCst), decoded_with_gil.load(Ordering::SeqCst));
+        encoded_with_gil.store(true, Ordering::SeqCst);
+        decoded_with_gil.store(true, Ordering::SeqCst);
+    });
+    held
+}
+
+#[test]
+fn releases_gil_by_default() {
+    let flags = (&RELEASED_ENCODED_WITH_GIL, &RELEASED_DECODED_WITH_GIL);
+    for code in [
+        "assert Released.FromString(Released(7).SerializeToString()).value == 7",
+        "m = Released(0)\nassert m.ParseFromString(Released(7).SerializeToString()) == 2 and m.value == 7",
+        "m = Released(0)\nassert m.MergeFromString(memoryview(Released(7).SerializeDelimited())[1:]) == 2",
+    ] {
+        assert_eq!(run(code, flags.0, flags.1), (false, false), "{}", code);
+    }
+}
+
+#[test]
+fn decodes_writable_buffers_holding_the_gil() {
+    let flags = (&RELEASED_ENCODED_WITH_GIL, &RELEASED_DECODED_WITH_GIL);
+    for code in [
+        "m = Released(0)\nassert m.ParseFromString(bytearray(Released(7).SerializeToString())) == 2",
+        "m = Released(0)\nassert m.MergeFromString(memoryview(bytearray(Released(7).SerializeToString()))) == 2",
+        "import mmap\nm = mmap.mmap(-1, 2)\nm.write(Released(7).SerializeToString())\nassert Released.FromString(m).value == 7",
+    ] {
+        assert!(run(code, flags.0, flags.1).1, "{}", code);
+    }
+}
+
+#[test]
+fn hold_gil() {
+    let flags = (&HELD_ENCODED_WITH_GIL, &HELD_DECODED_WITH_GIL);
+    for code in [
+        "assert Held.FromString(Held(7).SerializeToString()).value == 7",
+        "m = Held(0)\nassert m.ParseFromString(bytearray(Held(7).SerializeToString())) == 2 and m.value == 7",
+        "m = Held(0)\nassert m.ParseDelimited(Held(7).SerializeDelimited()) == 3 and m.value == 7",
+    ] {
+        assert_eq!(run(code, flags.0, flags.1), (true, true), "{}", code);
+    }
+}
@@ -0,0 +1,65 @@
+mod common;
+mod echo;
+
+use echo::Msg;
+use pyo3::prelude::*;
+
+echo_client!(#[pyo3_macro::with_grpc_client(asyncio)] echo_client);
+
+fn run(code: &str) {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            code,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn unary_calls() {
+    run(r#"
+import asyncio
+
+async def main():
+    client = EchoClient(addr)
+    reply = await client.unary(Msg("hi", 1))
+    assert (reply.text, reply.count) == ("hi!", 2)
+    try:
+        await client.unary(Msg("missing", 1))
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError as err:
+        assert "no such text" in str(err), err
+
+asyncio.run(main())
+"#);
+}
+
+#[test]
+fn streaming_calls() {
+    run(r#"
+import asyncio
+
+async def requests():
+    for text in ("a", "b"):
+        await asyncio.sleep(0)
+        yield Msg(text, 1)
+
+async def main():
+    client = EchoClient(addr)
+    assert [msg.count async for msg in await client.expand(Msg("x", 3))] == [0, 1, 2]
+    # client streams are read from async iterables and plain ones
+    total = await client.collect(requests())
+    assert (total.text, total.count) == ("ab", 2)
+    total = await client.collect([Msg("c", 1)])
+    assert (total.text, total.count) == ("c", 1)
+    assert [msg.text async for msg in await client.chat(requests())] == ["A", "B"]
+
+asyncio.run(main())
+"#);
+}
@@ -0,0 +1,143 @@
+mod common;
+mod echo;
+
+use echo::Msg;
+use pyo3::prelude::*;
+
+echo_client!(#[pyo3_macro::with_grpc_client] echo_client);
+
+#[test]
+fn unary_calls() {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+client = EchoClient(addr)
+reply = client.unary(Msg("hi", 1))
+assert (reply.text, reply.count) == ("hi!", 2)
+try:
+    client.unary(Msg("missing", 1))
+    raise AssertionError("expected RuntimeError")
+except RuntimeError as err:
+    assert "no such text" in str(err), err
+assert [(name, client_streaming, server_streaming) for name, _, _, client_streaming, server_streaming in EchoClient.list_all_async_methods()] == [
+    ("unary", False, False), ("collect", True, False), ("expand", False, True), ("chat", True, True)
+]
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn server_streaming_calls() {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+client = EchoClient(addr)
+stream = client.expand(Msg("x", 3))
+assert iter(stream) is stream
+assert [(msg.text, msg.count) for msg in stream] == [("x", 0), ("x", 1), ("x", 2)]
+assert list(stream) == []
+assert list(client.expand(Msg("x", 0))) == []
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn client_streaming_calls() {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+client = EchoClient(addr)
+reply = client.collect([Msg("a", 1), Msg("b", 2)])
+assert (reply.text, reply.count) == ("ab", 3)
+reply = client.collect(Msg(str(i), i) for i in range(4))
+assert (reply.text, reply.count) == ("0123", 6)
+assert client.collect([]).count == 0
+replies = client.chat(iter([Msg("a", 1), Msg("b", 2)]))
+assert [(msg.text, msg.count) for msg in replies] == [("A", 1), ("B", 2)]
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn request_iterable_errors() {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+client = EchoClient(addr)
+
+def failing():
+    yield Msg("a", 1)
+    raise KeyError("no more requests")
+
+try:
+    client.collect(failing())
+    raise AssertionError("expected KeyError")
+except KeyError as err:
+    assert err.args == ("no more requests",), err
+try:
+    list(client.chat(failing()))
+    raise AssertionError("expected KeyError")
+except KeyError as err:
+    assert err.args == ("no more requests",), err
+try:
+    client.collect([Msg("a", 1), "not a message"])
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+try:
+    client.collect(42)
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn connection_errors() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+try:
+    EchoClient("not a uri")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#,
+            &[("EchoClient", common::class::<echo_client::PyEchoClient>(py))],
+        );
+    });
+}
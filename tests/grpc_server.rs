@@ -0,0 +1,124 @@
+mod common;
+mod echo;
+
+use echo::Msg;
+use pyo3::prelude::*;
+
+echo_client!(#[pyo3_macro::with_grpc_client] echo_client);
+echo_server!(#[pyo3_macro::with_grpc_server] py_echo_server);
+
+// Serves the Python servicer `servicer` and connects `client` to it, run before the test code.
+const SERVE: &str = r#"
+import socket, threading, time
+sock = socket.socket(); sock.bind(("127.0.0.1", 0)); port = sock.getsockname()[1]; sock.close()
+threading.Thread(target=EchoServer(servicer).serve, args=(f"127.0.0.1:{port}",), daemon=True).start()
+for _ in range(50):
+    try:
+        client = EchoClient(f"http://127.0.0.1:{port}")
+        break
+    except ConnectionError:
+        time.sleep(0.1)
+"#;
+
+fn run_servicer(servicer: &str, code: &str) {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            &format!("{}\nservicer = Servicer()\n{}\n{}", servicer, SERVE, code),
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("EchoServer", common::class::<py_echo_server::PyEchoServer>(py)),
+                ("Msg", common::class::<Msg>(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn unary_handlers() {
+    run_servicer(
+        r#"
+class Servicer:
+    def unary(self, msg):
+        if msg.text == "missing":
+            raise KeyError("no such text")
+        if msg.text == "bad":
+            raise ValueError("bad text")
+        return Msg(msg.text * 2, msg.count)
+"#,
+        r#"
+assert client.unary(Msg("ab", 1)).text == "abab"
+for text, code in [("missing", "NotFound"), ("bad", "InvalidArgument")]:
+    try:
+        client.unary(Msg(text, 1))
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError as err:
+        assert code in str(err), err
+try:
+    client.collect([Msg("a", 1)])
+    raise AssertionError("expected RuntimeError")
+except RuntimeError as err:
+    assert "Unimplemented" in str(err), err
+"#,
+    );
+}
+
+#[test]
+fn streaming_handlers() {
+    run_servicer(
+        r#"
+class Servicer:
+    def collect(self, msgs):
+        assert iter(msgs) is msgs
+        msgs = list(msgs)
+        return Msg("".join(msg.text for msg in msgs), sum(msg.count for msg in msgs))
+
+    def expand(self, msg):
+        for count in range(msg.count):
+            yield Msg(msg.text, count)
+
+    def chat(self, msgs):
+        for msg in msgs:
+            if msg.text == "stop":
+                raise PermissionError("stopped")
+            yield Msg(msg.text.upper(), msg.count)
+"#,
+        r#"
+reply = client.collect([Msg("a", 1), Msg("b", 2)])
+assert (reply.text, reply.count) == ("ab", 3)
+assert [msg.count for msg in client.expand(Msg("x", 3))] == [0, 1, 2]
+assert [msg.text for msg in client.chat([Msg("a", 1), Msg("b", 2)])] == ["A", "B"]
+received = []
+try:
+    for msg in client.chat([Msg("a", 1), Msg("stop", 2), Msg("c", 3)]):
+        received.append(msg.text)
+    raise AssertionError("expected RuntimeError")
+except RuntimeError as err:
+    assert "PermissionDenied" in str(err), err
+# tonic drops the responses encoded in the same batch as the error status
+assert received in ([], ["A"]), received
+"#,
+    );
+}
+
+#[test]
+fn invalid_responses() {
+    run_servicer(
+        r#"
+class Servicer:
+    def unary(self, msg):
+        return "not a message"
+
+    def expand(self, msg):
+        return 42
+"#,
+        r#"
+for call in (lambda: client.unary(Msg("a", 1)), lambda: list(client.expand(Msg("a", 1)))):
+    try:
+        call()
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError as err:
+        assert "InvalidArgument" in str(err), err
+"#,
+    );
+}
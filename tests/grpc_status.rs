@@ -0,0 +1,103 @@
+mod common;
+mod echo;
+
+use echo::Msg;
+use pyo3::prelude::*;
+
+#[pyo3_macro::with_grpc_status]
+pub mod status {}
+
+echo_client!(#[pyo3_macro::with_grpc_client(status_error = crate::status::StatusError)] echo_client);
+echo_server!(#[pyo3_macro::with_grpc_server(status_error = crate::status::StatusError)] py_echo_server);
+
+// Module holding the status exceptions of `with_grpc_status`.
+fn status_module(py: Python<'_>) -> PyObject {
+    let module = pyo3::types::PyModule::new_bound(py, "status").unwrap();
+    status::add_status_errors(&module).unwrap();
+    module.into_any().unbind()
+}
+
+#[test]
+fn exception_classes() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+err = status.NotFoundError("gone")
+assert isinstance(err, status.RpcError) and isinstance(err, Exception)
+assert (err.code(), err.details(), err.trailing_metadata()) == (status.StatusCode.NOT_FOUND, "gone", [])
+assert str(err) == "NotFound: gone"
+assert status.RpcError(status.StatusCode.ABORTED).code() == status.StatusCode.ABORTED
+assert not isinstance(status.UnavailableError(), status.NotFoundError)
+"#,
+            &[("status", status_module(py))],
+        );
+    });
+}
+
+#[test]
+fn client_raises_status_errors() {
+    let addr = echo::serve();
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+client = EchoClient(addr)
+try:
+    client.unary(Msg("missing", 1))
+    raise AssertionError("expected NotFoundError")
+except status.NotFoundError as err:
+    assert (err.code(), err.details()) == (status.StatusCode.NOT_FOUND, "no such text")
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("status", status_module(py)),
+                ("addr", addr.into_py(py)),
+            ],
+        );
+    });
+}
+
+#[test]
+fn servicer_raises_status_errors() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+import socket, threading, time
+sock = socket.socket(); sock.bind(("127.0.0.1", 0)); port = sock.getsockname()[1]; sock.close()
+
+class Servicer:
+    def unary(self, msg):
+        if msg.text == "busy":
+            raise status.ResourceExhaustedError("try later")
+        raise KeyError(msg.text)
+
+threading.Thread(target=EchoServer(Servicer()).serve, args=(f"127.0.0.1:{port}",), daemon=True).start()
+for _ in range(50):
+    try:
+        client = EchoClient(f"http://127.0.0.1:{port}")
+        break
+    except ConnectionError:
+        time.sleep(0.1)
+try:
+    client.unary(Msg("busy", 1))
+    raise AssertionError("expected ResourceExhaustedError")
+except status.ResourceExhaustedError as err:
+    assert err.details() == "try later"
+try:
+    client.unary(Msg("other", 1))
+    raise AssertionError("expected NotFoundError")
+except status.NotFoundError:
+    pass
+"#,
+            &[
+                ("EchoClient", common::class::<echo_client::PyEchoClient>(py)),
+                ("EchoServer", common::class::<py_echo_server::PyEchoServer>(py)),
+                ("Msg", common::class::<Msg>(py)),
+                ("status", status_module(py)),
+            ],
+        );
+    });
+}
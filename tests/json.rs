@@ -0,0 +1,248 @@
+mod common;
+
+use pyo3::prelude::*;
+
+// Well-known types like `compile_well_known_types()` generates them.
+pub mod google {
+    pub mod protobuf {
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+        pub struct Timestamp {
+            #[prost(int64, tag = "1")]
+            pub seconds: i64,
+            #[prost(int32, tag = "2")]
+            pub nanos: i32,
+        }
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+        pub struct Duration {
+            #[prost(int64, tag = "1")]
+            pub seconds: i64,
+            #[prost(int32, tag = "2")]
+            pub nanos: i32,
+        }
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+        pub struct Int64Value {
+            #[prost(int64, tag = "1")]
+            pub value: i64,
+        }
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+        pub struct FieldMask {
+            #[prost(string, repeated, tag = "1")]
+            pub paths: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        }
+    }
+}
+
+use google::protobuf;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Event {
+    #[prost(string, tag = "1")]
+    pub display_name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub big: i64,
+    #[prost(uint32, tag = "3")]
+    pub small: u32,
+    #[prost(double, tag = "4")]
+    pub score: f64,
+    #[prost(bool, tag = "5")]
+    pub flag: bool,
+    #[prost(bytes = "vec", tag = "6")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+    #[prost(enumeration = "Mood", tag = "7")]
+    pub mood: i32,
+    #[prost(int64, repeated, tag = "8")]
+    pub bigs: ::prost::alloc::vec::Vec<i64>,
+    #[prost(map = "string, int64", tag = "9")]
+    pub limits: ::std::collections::HashMap<::prost::alloc::string::String, i64>,
+    #[prost(message, optional, tag = "10")]
+    pub owner: ::core::option::Option<Owner>,
+    #[prost(string, optional, tag = "11")]
+    pub note: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "12")]
+    pub at: ::core::option::Option<protobuf::Timestamp>,
+    #[prost(message, optional, tag = "13")]
+    pub took: ::core::option::Option<protobuf::Duration>,
+    #[prost(message, optional, tag = "14")]
+    pub count: ::core::option::Option<protobuf::Int64Value>,
+    #[prost(message, optional, tag = "15")]
+    pub mask: ::core::option::Option<protobuf::FieldMask>,
+    #[prost(oneof = "event::Pick", tags = "16, 17")]
+    pub pick: ::core::option::Option<event::Pick>,
+}
+/// Nested message and enum types in `Event`.
+pub mod event {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Pick {
+        #[prost(int64, tag = "16")]
+        PickedNumber(i64),
+        #[prost(message, tag = "17")]
+        PickedOwner(super::Owner),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Owner {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub id: i64,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Mood {
+    Unspecified = 0,
+    Happy = 1,
+    Sad = 2,
+}
+impl Mood {
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Mood::Unspecified => "MOOD_UNSPECIFIED",
+            Mood::Happy => "HAPPY",
+            Mood::Sad => "SAD",
+        }
+    }
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "MOOD_UNSPECIFIED" => Some(Self::Unspecified),
+            "HAPPY" => Some(Self::Happy),
+            "SAD" => Some(Self::Sad),
+            _ => None,
+        }
+    }
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        common::run_python(py, code, &[("Event", common::class::<Event>(py)), ("Owner", common::class::<Owner>(py))]);
+    });
+}
+
+#[test]
+fn to_json() {
+    Python::with_gil(|py| {
+        let event = Event {
+            display_name: "héllo".into(),
+            big: -9007199254740993,
+            small: 7,
+            score: f64::INFINITY,
+            flag: true,
+            data: b"\xfb\xff".to_vec(),
+            mood: Mood::Happy as i32,
+            bigs: vec![1, 2],
+            limits: [("cpu".to_string(), 4)].into_iter().collect(),
+            owner: Some(Owner { name: "o".into(), id: 12 }),
+            note: Some(String::new()),
+            at: Some(protobuf::Timestamp { seconds: 1_700_000_000, nanos: 10_000_000 }),
+            took: Some(protobuf::Duration { seconds: -1, nanos: -500_000_000 }),
+            count: Some(protobuf::Int64Value { value: 3 }),
+            mask: Some(protobuf::FieldMask { paths: vec!["display_name".into(), "owner.name".into()] }),
+            pick: Some(event::Pick::PickedOwner(Owner { name: "p".into(), id: 0 })),
+        };
+        let expected = concat!(
+            r#"{"displayName": "héllo", "big": "-9007199254740993", "small": 7, "score": "Infinity", "flag": true, "#,
+            r#""data": "+/8=", "mood": "HAPPY", "bigs": ["1", "2"], "limits": {"cpu": "4"}, "#,
+            r#""owner": {"name": "o", "id": "12"}, "note": "", "at": "2023-11-14T22:13:20.010Z", "took": "-1.500s", "#,
+            r#""count": "3", "mask": "displayName,owner.name", "pickedOwner": {"name": "p"}}"#,
+        );
+        let json = event.to_json(py, None, false, false).unwrap();
+        assert_eq!(json, expected);
+        assert!(Event::from_json(py, &json).unwrap() == event);
+    });
+    run(r#"
+# default values are left out, like python protobuf does
+assert Owner("", 0).to_json() == "{}"
+assert Owner("a", 1).to_json(indent=None) == '{"name": "a", "id": "1"}'
+assert Owner("a", 1).to_json() == '{\n  "name": "a",\n  "id": "1"\n}'
+"#);
+}
+
+#[test]
+fn from_json() {
+    run(r#"
+e = Event.from_json('{"display_name": "t", "big": 5, "small": "6", "score": "NaN", "mood": 2, "bigs": null,'
+                    ' "data": "-_8", "at": "1970-01-01T01:00:00+01:00", "took": "0.000001s", "pickedNumber": "9"}')
+# proto field names and numbers in strings are accepted too
+assert (e.display_name, e.big, e.small, e.mood, bytes(e.data)) == ("t", 5, 6, 2, b"\xfb\xff")
+assert e.score != e.score and e.picked_number == 9
+assert (e.at.seconds, e.took.nanos) == (0, 1000)
+assert Event.from_json(e.to_json()).to_json() == e.to_json()
+"#);
+}
+
+#[test]
+fn invalid_json() {
+    run(r#"
+for bad in ['{"unknown": 1}', '{"mood": "ANGRY"}', '{"big": "x"}', '{"at": "yesterday"}', '[]', '{']:
+    try:
+        Event.from_json(bad)
+    except (ValueError, TypeError):
+        pass
+    else:
+        raise AssertionError(bad)
+"#);
+}
+
+#[test]
+fn to_dict_and_from_dict() {
+    run(r#"
+e = Event.from_dict({"display_name": "t", "bigs": [1, 2], "limits": {"a": 3}, "owner": {"name": "o"}, "mood": "SAD",
+                     "picked_owner": {"id": "4"}})
+assert e.to_dict() == {"displayName": "t", "mood": "SAD", "bigs": ["1", "2"], "limits": {"a": "3"},
+                       "owner": {"name": "o"}, "pickedOwner": {"id": "4"}}, e.to_dict()
+d = e.to_dict(preserving_proto_field_name=True)
+assert d["display_name"] == "t" and d["picked_owner"] == {"id": "4"}, d
+assert Event.from_dict(d).to_dict() == e.to_dict()
+
+# fields with presence are still left out
+d = Event.from_dict({}).to_dict(including_default_value_fields=True)
+assert (d["displayName"], d["big"], d["mood"], d["flag"], d["bigs"], d["limits"]) == ("", "0", "MOOD_UNSPECIFIED", False, [], {}), d
+assert not {"owner", "note", "at", "pickedOwner"} & d.keys(), d
+d = Event.from_dict({"owner": {}}).to_dict(preserving_proto_field_name=True, including_default_value_fields=True)
+assert d["owner"] == {"name": "", "id": "0"} and d["display_name"] == "", d
+assert Owner("a", 1).to_json(indent=None, preserving_proto_field_name=True) == '{"name": "a", "id": "1"}'
+
+for bad in [{"unknown": 1}, {"owner": {"nope": 1}}]:
+    try:
+        Event.from_dict(bad)
+    except ValueError:
+        pass
+    else:
+        raise AssertionError(bad)
+"#);
+}
+
+#[test]
+fn json_type_errors() {
+    run(r#"
+for json, field in [
+    ('{"flag": 1}', "flag"),
+    ('{"big": 1.5}', "big"),
+    ('{"small": 4294967296}', "small"),
+    ('{"owner": {"id": 1.5}}', "owner"),
+    ('{"pickedOwner": {"name": 5}}', "pickedOwner"),
+]:
+    try:
+        Event.from_json(json)
+        raise AssertionError("expected ValueError")
+    except ValueError as err:
+        assert str(err).startswith(f"Failed to parse {field} field: "), err
+try:
+    Event.from_dict({"owner": {"id": 2**63}})
+    raise AssertionError("expected ValueError")
+except ValueError as err:
+    assert "id" in str(err), err
+"#);
+}
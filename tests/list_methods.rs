@@ -0,0 +1,18 @@
+mod echo;
+
+use echo::Msg;
+
+echo_client!(#[pyo3_macro::list_all_async_methods] echo_client);
+
+#[test]
+fn lists_async_rpcs() {
+    assert_eq!(
+        echo_client::list_all_async_methods(),
+        vec![
+            ("unary", "super::Msg", "super::Msg", false, false),
+            ("collect", "super::Msg", "super::Msg", true, false),
+            ("expand", "super::Msg", "super::Msg", false, true),
+            ("chat", "super::Msg", "super::Msg", true, true),
+        ]
+    );
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Order {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub total: i64,
+    #[prost(string, optional, tag = "3")]
+    pub note: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "4")]
+    pub lines: ::prost::alloc::vec::Vec<Line>,
+    #[prost(map = "string, int32", tag = "5")]
+    pub tags: ::std::collections::HashMap<::prost::alloc::string::String, i32>,
+    #[prost(message, optional, tag = "6")]
+    pub shipping: ::core::option::Option<Line>,
+    #[prost(oneof = "order::Payment", tags = "7, 8")]
+    pub payment: ::core::option::Option<order::Payment>,
+}
+/// Nested message and enum types in `Order`.
+pub mod order {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Payment {
+        #[prost(string, tag = "7")]
+        Card(::prost::alloc::string::String),
+        #[prost(message, tag = "8")]
+        Voucher(super::Line),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Line {
+    #[prost(string, tag = "1")]
+    pub sku: ::prost::alloc::string::String,
+    #[prost(int32, tag = "2")]
+    pub quantity: i32,
+}
+
+// Well-known types like `prost_types::Timestamp` don't derive `WithNew`.
+pub mod google {
+    pub mod protobuf {
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message)]
+        pub struct Timestamp {
+            #[prost(int64, tag = "1")]
+            pub seconds: i64,
+            #[prost(int32, tag = "2")]
+            pub nanos: i32,
+        }
+    }
+}
+
+use google::protobuf::Timestamp;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Event {
+    #[prost(message, optional, tag = "1")]
+    pub at: ::core::option::Option<google::protobuf::Timestamp>,
+    #[prost(oneof = "event::When", tags = "2, 3")]
+    pub when: ::core::option::Option<event::When>,
+}
+/// Nested message and enum types in `Event`.
+pub mod event {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum When {
+        #[prost(message, tag = "2")]
+        Start(super::google::protobuf::Timestamp),
+        #[prost(string, tag = "3")]
+        Label(::prost::alloc::string::String),
+    }
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        common::run_python(py, code, &[("Order", common::class::<Order>(py)), ("Line", common::class::<Line>(py))]);
+    });
+}
+
+#[test]
+fn merge_from() {
+    run(r#"
+a = Order("a", 5, [Line("x", 1)], {"k": 1, "j": 2}, note="n", shipping=Line("ship", 0))
+b = Order("", 7, [Line("y", 2)], {"k": 3}, shipping=Line("", 4))
+a.MergeFrom(b)
+# set scalars overwrite, default ones and unset optionals don't
+assert (a.id, a.total, a.note) == ("a", 7, "n")
+# repeated fields append and maps merge by key
+assert [(line.sku, line.quantity) for line in a.lines] == [("x", 1), ("y", 2)]
+assert a.tags == {"k": 3, "j": 2}
+# sub-messages merge recursively
+assert (a.shipping.sku, a.shipping.quantity) == ("ship", 4)
+# `b` is left untouched
+assert [line.sku for line in b.lines] == ["y"] and b.shipping.sku == ""
+a.MergeFrom(a)
+assert len(a.lines) == 4
+"#);
+}
+
+#[test]
+fn merge_oneofs() {
+    run(r#"
+a = Order("a", 0, [], {}, voucher=Line("v", 1))
+a.MergeFrom(Order("", 0, [], {}, voucher=Line("", 3)))
+assert (a.voucher.sku, a.voucher.quantity) == ("v", 3)
+# other variants replace the set one
+a.MergeFrom(Order("", 0, [], {}, card="4242"))
+assert a.WhichOneof("payment") == "card" and a.card == "4242"
+a.MergeFrom(Order("", 0, [], {}, voucher=Line("w", 0)))
+assert a.WhichOneof("payment") == "voucher" and a.voucher.sku == "w"
+a.MergeFrom(Order("", 0, [], {}))
+assert a.WhichOneof("payment") == "voucher"
+"#);
+}
+
+#[test]
+fn copy_from() {
+    run(r#"
+a = Order("a", 5, [Line("x", 1)], {"k": 1}, note="n", card="1")
+b = Order("b", 0, [], {})
+a.CopyFrom(b)
+assert (a.id, a.total, a.lines, a.tags, a.note, a.WhichOneof("payment")) == ("b", 0, [], {}, None, None)
+b.id = "c"
+assert a.id == "b"
+"#);
+}
+
+#[test]
+fn merge_from_rust() {
+    let mut order = Order { id: "a".into(), lines: vec![Line { sku: "x".into(), quantity: 1 }], ..Default::default() };
+    order.merge_from(&Order { total: 3, lines: vec![Line { sku: "y".into(), quantity: 1 }], ..Default::default() });
+    assert_eq!((order.id.as_str(), order.total, order.lines.len()), ("a", 3, 2));
+}
+
+#[test]
+fn merge_well_known_types() {
+    let stamp = |seconds, nanos| Timestamp { seconds, nanos };
+    let mut event = Event { at: Some(stamp(1, 2)), when: Some(event::When::Start(stamp(5, 6))) };
+    event.merge_from(&Event { at: Some(stamp(0, 7)), when: Some(event::When::Start(stamp(8, 0))) });
+    assert_eq!(event.at, Some(stamp(1, 7)));
+    assert_eq!(event.when, Some(event::When::Start(stamp(8, 6))));
+    event.merge_from(&Event { at: None, when: Some(event::When::Label("later".into())) });
+    assert_eq!((event.at, event.when), (Some(stamp(1, 7)), Some(event::When::Label("later".into()))));
+
+    let mut event = Event::default();
+    event.merge_from(&Event { at: Some(stamp(1, 0)), when: None });
+    assert_eq!(event.at, Some(stamp(1, 0)));
+}
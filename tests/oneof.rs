@@ -0,0 +1,126 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Order {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(oneof = "order::Payment", tags = "2, 3")]
+    pub payment: ::core::option::Option<order::Payment>,
+}
+/// Nested message and enum types in `Order`.
+pub mod order {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Payment {
+        #[prost(string, tag = "2")]
+        Card(::prost::alloc::string::String),
+        #[prost(message, tag = "3")]
+        Voucher(super::Line),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Line {
+    #[prost(string, tag = "1")]
+    pub sku: ::prost::alloc::string::String,
+}
+
+#[test]
+fn oneof_attributes() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+o = Order("o-1", card="4242")
+assert o.WhichOneof("payment") == "card" and o.card == "4242"
+assert o.voucher.sku == ""
+o.voucher = Line("gift")
+assert o.WhichOneof("payment") == "voucher" and o.voucher.sku == "gift"
+assert o.card == ""
+o.id = "o-2"
+assert o.id == "o-2" and o.WhichOneof("payment") == "voucher"
+o.card = None
+assert o.WhichOneof("payment") == "voucher"
+del o.voucher
+assert o.WhichOneof("payment") is None
+assert Order("o-3").WhichOneof("payment") is None
+try:
+    o.WhichOneof("kind")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+try:
+    o.card = 42
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+try:
+    o.missing
+    raise AssertionError("expected AttributeError")
+except AttributeError:
+    pass
+"#,
+            &[("Order", common::class::<Order>(py)), ("Line", common::class::<Line>(py))],
+        );
+    });
+}
+
+#[test]
+fn other_attributes() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+o = Order("o-1", card="4242")
+o.id = "o-2"
+assert o.id == "o-2" and o.card == "4242"
+# other attributes go to the instance `__dict__`
+o.extra = 1
+assert o.extra == 1 and o.id == "o-2"
+del o.extra
+try:
+    del o.extra
+    raise AssertionError("expected AttributeError")
+except AttributeError:
+    pass
+try:
+    del o.id
+    raise AssertionError("expected an error deleting a field")
+except (AttributeError, TypeError):
+    pass
+
+# properties of python subclasses are set through their descriptors
+class Tagged(Order):
+    @property
+    def label(self):
+        return self.id.upper()
+
+    @label.setter
+    def label(self, value):
+        self.id = value.lower()
+
+t = Tagged("o-3")
+t.label = "X"
+t.card = "1"
+assert (t.id, t.label, t.WhichOneof("payment")) == ("x", "X", "card")
+"#,
+            &[("Order", common::class::<Order>(py))],
+        );
+    });
+}
+
+#[test]
+fn oneof_round_trip() {
+    let order = Order { id: "o-1".into(), payment: Some(order::Payment::Voucher(Line { sku: "gift".into() })) };
+    Python::with_gil(|py| {
+        let object = Py::new(py, order.clone()).unwrap();
+        assert_eq!(object.call_method1(py, "WhichOneof", ("payment",)).unwrap().extract::<String>(py).unwrap(), "voucher");
+        object.setattr(py, "card", "4242").unwrap();
+        assert_eq!(object.borrow(py).payment, Some(order::Payment::Card("4242".into())));
+    });
+}
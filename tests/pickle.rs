@@ -0,0 +1,184 @@
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass(module = "pickled")]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+#[with_new(module = "pickled")]
+pub struct Config {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub tags: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(map = "string, int64", tag = "3")]
+    pub limits: ::std::collections::HashMap<::prost::alloc::string::String, i64>,
+    #[prost(message, optional, tag = "4")]
+    pub owner: ::core::option::Option<Owner>,
+    #[prost(oneof = "config::Choice", tags = "5, 6")]
+    pub choice: ::core::option::Option<config::Choice>,
+}
+/// Nested message and enum types in `Config`.
+pub mod config {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    #[with_new(module = "pickled")]
+    pub enum Choice {
+        #[prost(string, tag = "5")]
+        Word(::prost::alloc::string::String),
+        #[prost(message, tag = "6")]
+        Sub(super::Owner),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass(module = "pickled")]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Owner {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub id: i64,
+}
+
+#[pyo3_macro::with_pyclass(module = "pickled")]
+#[derive(Clone, Copy, PartialEq, pyo3_macro::WithNew)]
+pub enum Color {
+    Red = 1,
+    Green = 4,
+}
+
+#[derive(Clone, Debug, PartialEq, pyo3_macro::WithNew)]
+#[with_new(concrete(Pair<i64> = "IntPair"), module = "pickled")]
+pub struct Pair<T> {
+    pub name: String,
+    pub values: Vec<T>,
+    pub first: Option<T>,
+}
+
+// Neither derives `WithNew`, `with_pyclass` makes them picklable
+#[pyo3_macro::with_pyclass(module = "pickled")]
+#[derive(Clone)]
+pub struct Settings {
+    pub name: String,
+    pub ports: Vec<i64>,
+    pub owner: Option<Owner>,
+}
+
+#[pyo3_macro::with_pyclass(module = "pickled")]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+// Pickle imports the classes from their module, so they are registered as the module `pickled`.
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        let module = PyModule::new_bound(py, "pickled").unwrap();
+        module.add_class::<Config>().unwrap();
+        module.add_class::<Owner>().unwrap();
+        module.add_class::<config::PyChoice>().unwrap();
+        module.add_class::<Color>().unwrap();
+        module.add_class::<PyIntPair>().unwrap();
+        module.add_class::<Settings>().unwrap();
+        module.add_class::<Level>().unwrap();
+        py.import_bound("sys").unwrap().getattr("modules").unwrap().set_item("pickled", &module).unwrap();
+        if let Err(err) = py.run_bound(code, None, None) {
+            err.print(py);
+            panic!("Python code failed: {}", err);
+        }
+    });
+}
+
+#[test]
+fn pickle_messages() {
+    run(r#"
+import copy, pickle
+from pickled import Config, Owner, Choice
+
+c = Config("a", ["x"], {"cpu": 1}, Owner("o", 1), word="w")
+for protocol in range(2, pickle.HIGHEST_PROTOCOL + 1):
+    d = pickle.loads(pickle.dumps(c, protocol))
+    assert type(d) is Config and d.SerializeToString() == c.SerializeToString()
+    assert (d.name, d.tags, d.limits, d.owner.name, d.word) == ("a", ["x"], {"cpu": 1}, "o", "w")
+assert c.__getstate__() == c.SerializeToString()
+e = Config("", [], {})
+e.__setstate__(c.__getstate__())
+assert (e.name, e.word) == ("a", "w")
+"#);
+}
+
+#[test]
+fn copy_messages() {
+    run(r#"
+import copy
+from pickled import Config, Owner
+
+c = Config("a", ["x"], {}, Owner("o", 1))
+for d in (copy.copy(c), copy.deepcopy(c)):
+    d.tags = ["y"]
+    d.owner.name = "p"
+    assert c.tags == ["x"] and c.owner.name == "o"
+    assert d.SerializeToString() != c.SerializeToString()
+
+# copies keep the class of python subclasses
+class Sub(Config):
+    pass
+s = Sub("s", [], {})
+for t in (copy.copy(s), copy.deepcopy(s)):
+    assert type(t) is Sub and t.name == "s"
+"#);
+}
+
+#[test]
+fn pickle_enums_and_concrete_classes() {
+    run(r#"
+import copy, pickle
+from pickled import Color, Choice, Owner, IntPair
+
+assert [pickle.loads(pickle.dumps(color)) for color in (Color.Red, Color.Green)] == [Color.Red, Color.Green]
+assert copy.copy(Color.Green) is Color.Green and copy.deepcopy(Color.Green) is Color.Green
+
+for choice in (Choice.Word("w"), Choice.Sub(Owner("s", 2))):
+    loaded = pickle.loads(pickle.dumps(choice))
+    assert (type(loaded), loaded.variant) == (Choice, choice.variant)
+    assert copy.copy(choice).variant == copy.deepcopy(choice).variant == choice.variant
+assert pickle.loads(pickle.dumps(Choice.Sub(Owner("s", 2)))).value.name == "s"
+w = Choice.Word("w")
+w.__setstate__(Choice.Sub(Owner("s", 2)).__getstate__())
+assert (w.variant, w.value.id) == ("Sub", 2)
+
+p = IntPair("p", [1, 2], 3)
+q = pickle.loads(pickle.dumps(p))
+assert (type(q), q.name, q.values, q.first) == (IntPair, "p", [1, 2], 3)
+assert p.__getstate__() == ("p", [1, 2], 3)
+r = copy.deepcopy(p)
+r.values = [4]
+assert p.values == [1, 2] and r.name == "p"
+r.__setstate__(("r", [], None))
+assert (r.name, r.values, r.first) == ("r", [], None)
+"#);
+}
+
+#[test]
+fn pickle_with_pyclass_only_types() {
+    run(r#"
+import copy, pickle
+from pickled import Settings, Level, Owner
+
+# without a constructor, instances are built from their state
+settings = Settings._from_state(("s", [80, 443], Owner("o", 1)))
+
+for protocol in range(2, pickle.HIGHEST_PROTOCOL + 1):
+    loaded = pickle.loads(pickle.dumps(settings, protocol))
+    assert (type(loaded), loaded.name, loaded.ports, loaded.owner.name) == (Settings, "s", [80, 443], "o")
+assert settings.__getstate__()[:2] == ("s", [80, 443])
+for copied in (copy.copy(settings), copy.deepcopy(settings)):
+    assert type(copied) is Settings and copied is not settings
+    copied.ports = [8080]
+    assert settings.ports == [80, 443] and copied.name == "s"
+loaded.__setstate__(("t", [], None))
+assert (loaded.name, loaded.ports, loaded.owner) == ("t", [], None)
+
+assert [pickle.loads(pickle.dumps(level)) for level in (Level.Low, Level.High)] == [Level.Low, Level.High]
+assert copy.copy(Level.High) is Level.High and copy.deepcopy(Level.High) is Level.High
+"#);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Item {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "2")]
+    pub note: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "3")]
+    pub child: ::core::option::Option<Child>,
+    #[prost(int32, repeated, tag = "4")]
+    pub sizes: ::prost::alloc::vec::Vec<i32>,
+    #[prost(string, tag = "5")]
+    pub r#type: ::prost::alloc::string::String,
+    #[prost(oneof = "item::Price", tags = "6, 7")]
+    pub price: ::core::option::Option<item::Price>,
+}
+/// Nested message and enum types in `Item`.
+pub mod item {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Price {
+        #[prost(int64, tag = "6")]
+        Cents(i64),
+        #[prost(string, tag = "7")]
+        Label(::prost::alloc::string::String),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Child {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+}
+
+#[test]
+fn has_and_clear_field() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+i = Item("a", [], "")
+assert not i.HasField("note") and not i.HasField("child") and not i.HasField("price")
+i.note = ""
+assert i.HasField("note")
+i.child = Child(0)
+i.cents = 5
+assert i.HasField("child") and i.HasField("price") and i.HasField("cents") and not i.HasField("label")
+for field in ("name", "sizes", "type"):
+    try:
+        i.HasField(field)
+        raise AssertionError("expected ValueError")
+    except ValueError as err:
+        assert "nor marker for presence" in str(err), err
+try:
+    i.HasField("missing")
+    raise AssertionError("expected ValueError")
+except ValueError as err:
+    assert 'has no "missing" field' in str(err), err
+
+i.sizes = [1]
+i.type = "t"
+i.ClearField("note")
+i.ClearField("sizes")
+i.ClearField("type")
+i.ClearField("label")
+assert (i.note, i.sizes, i.type, i.WhichOneof("price")) == (None, [], "", "cents")
+i.ClearField("cents")
+assert i.WhichOneof("price") is None
+i.cents = 1
+i.ClearField("price")
+assert not i.HasField("price")
+try:
+    i.ClearField("missing")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#,
+            &[("Item", common::class::<Item>(py)), ("Child", common::class::<Child>(py))],
+        );
+    });
+}
+
+#[test]
+fn list_fields() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+assert Item("", [], "").ListFields() == []
+i = Item("a", [2], "t", note="", label="x")
+assert [name for name, _ in i.ListFields()] == ["name", "note", "sizes", "type", "label"]
+assert dict(i.ListFields()) == {"name": "a", "note": "", "sizes": [2], "type": "t", "label": "x"}
+"#,
+            &[("Item", common::class::<Item>(py)), ("Child", common::class::<Child>(py))],
+        );
+    });
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Point {
+    #[prost(int64, tag = "1")]
+    pub x: i64,
+    #[prost(string, optional, tag = "2")]
+    pub label: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(int64, repeated, tag = "3")]
+    pub path: ::prost::alloc::vec::Vec<i64>,
+}
+
+#[test]
+fn serialize_to_string() {
+    Python::with_gil(|py| {
+        let point = Py::new(py, Point { x: 3, label: Some("p".into()), path: vec![] }).unwrap();
+        let bytes = point.call_method0(py, "SerializeToString").unwrap();
+        let bytes = bytes.downcast_bound::<pyo3::types::PyBytes>(py).unwrap().as_bytes();
+        assert_eq!(bytes, prost::Message::encode_to_vec(&Point { x: 3, label: Some("p".into()), path: vec![] }));
+    });
+}
+
+#[test]
+fn constructor_and_parse() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+p = Point(7, [])
+assert (p.x, p.label) == (7, None)
+data = Point(-2, [], "q").SerializeToString()
+assert isinstance(data, bytes)
+assert p.ParseFromString(data) == len(data)
+assert (p.x, p.label) == (-2, "q")
+assert Point(0, []).SerializeToString() == b""
+"#,
+            &[("Point", common::class::<Point>(py))],
+        );
+    });
+}
+
+#[test]
+fn parse_in_place() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+p = Point(1, [1, 2], "old")
+data = Point(5, [3]).SerializeToString()
+# fields missing from `data` are reset, like python protobuf
+assert p.ParseFromString(data) == len(data)
+assert (p.x, p.path, p.label) == (5, [3], None)
+try:
+    p.ParseFromString(b"\x12\x05a")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+assert (p.x, p.path) == (5, [3])
+"#,
+            &[("Point", common::class::<Point>(py))],
+        );
+    });
+}
+
+#[test]
+fn merge_from_string() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+p = Point(1, [1, 2], "old")
+data = Point(0, [3], "new").SerializeToString()
+assert p.MergeFromString(data) == len(data)
+# set scalars overwrite, unset ones are kept and repeated fields append
+assert (p.x, p.path, p.label) == (1, [1, 2, 3], "new")
+assert p.MergeFromString(b"") == 0
+assert (p.x, p.path, p.label) == (1, [1, 2, 3], "new")
+"#,
+            &[("Point", common::class::<Point>(py))],
+        );
+    });
+}
+
+#[test]
+fn byte_size() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+assert Point(0, []).ByteSize() == 0
+p = Point(300, [1, 2], "abc")
+assert p.ByteSize() == len(p.SerializeToString())
+"#,
+            &[("Point", common::class::<Point>(py))],
+        );
+    });
+}
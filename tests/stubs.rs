@@ -0,0 +1,107 @@
+use pyo3::prelude::*;
+
+#[pyo3_macro::with_string]
+pub mod proto {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    #[with_new(repr, eq)]
+    pub struct Order {
+        #[prost(string, tag = "1")]
+        pub r#type: ::prost::alloc::string::String,
+        #[prost(map = "string, int64", tag = "2")]
+        pub counts: ::std::collections::HashMap<::prost::alloc::string::String, i64>,
+        #[prost(message, repeated, tag = "3")]
+        pub lines: ::prost::alloc::vec::Vec<Line>,
+        #[prost(message, optional, tag = "4")]
+        pub first: ::core::option::Option<Line>,
+        #[prost(oneof = "order::Pay", tags = "5, 6")]
+        pub pay: ::core::option::Option<order::Pay>,
+    }
+    /// Nested message and enum types in `Order`.
+    pub mod order {
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+        pub enum Pay {
+            #[prost(string, tag = "5")]
+            Card(::prost::alloc::string::String),
+            #[prost(message, tag = "6")]
+            Voucher(::prost::alloc::boxed::Box<super::Line>),
+        }
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    pub struct Line {
+        #[prost(bytes = "vec", tag = "1")]
+        pub sku: ::prost::alloc::vec::Vec<u8>,
+        #[prost(double, tag = "2")]
+        pub price: f64,
+    }
+    #[pyo3_macro::with_pyclass]
+    #[derive(pyo3_macro::WithNew, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[with_new(hash)]
+    #[repr(i32)]
+    pub enum Status {
+        Open = 0,
+        Closed = 1,
+    }
+
+    #[derive(Clone, pyo3_macro::WithNew)]
+    #[with_new(concrete(Pair<i64> = "IntPair"))]
+    pub struct Pair<T> {
+        pub left: T,
+        pub right: Option<T>,
+    }
+
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone)]
+    pub struct Plain {
+        pub flags: Vec<bool>,
+    }
+
+    // Derived through an imported name instead of its path
+    use pyo3_macro::WithNew;
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, WithNew)]
+    pub struct Imported {
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+    }
+}
+
+#[test]
+fn py_stubs() {
+    let stubs = proto::py_stubs();
+    for expected in [
+        "class Order:\n    type: str\n    counts: dict[str, int]\n    lines: list[Line]\n    first: Line | None\n    pay: Pay | None\n",
+        "    def __init__(self, type: str, counts: dict[str, int], lines: list[Line], first: Line | None = None, pay: Pay | None = None, **oneof_fields: typing.Any) -> None: ...\n",
+        "    def CopyFrom(self, other: Order) -> None: ...\n",
+        "    @staticmethod\n    def from_text(text: str) -> Order: ...\n",
+        "    def __repr__(self) -> str: ...\n    def __eq__(self, other: object) -> bool: ...\n",
+        "class Line:\n    sku: list[int]\n    price: float\n    def __init__(self, sku: list[int], price: float) -> None: ...\n",
+        "class Pay:\n    @staticmethod\n    def Card(value: str) -> Pay: ...\n    @staticmethod\n    def Voucher(value: Line) -> Pay: ...\n",
+        "class Status:\n    Open: typing.ClassVar[Status]\n    Closed: typing.ClassVar[Status]\n",
+        "    def __hash__(self) -> int: ...\n",
+        "class IntPair:\n    left: int\n    right: int | None\n    def __init__(self, left: int, right: int | None = None) -> None: ...\n",
+        "class Plain:\n    flags: list[bool]\n    def __reduce__(self) -> tuple[typing.Any, ...]: ...\n",
+        "class Imported:\n    id: int\n    def __init__(self, id: int) -> None: ...\n",
+        "def iter_delimited(reader: typing.BinaryIO, cls: type) -> DelimitedIterator: ...\n",
+    ] {
+        assert!(stubs.contains(expected), "missing {:?} in\n{}", expected, stubs);
+    }
+    // `__str__()` is opt-in
+    assert!(!stubs.contains("__str__"), "{}", stubs);
+    // The stubs are valid Python
+    Python::with_gil(|py| {
+        py.import_bound("ast").unwrap().call_method1("parse", (stubs,)).unwrap();
+    });
+}
+
+#[test]
+fn write_py_stubs() {
+    let path = std::env::temp_dir().join(format!("pyo3_macro_stubs_{}.pyi", std::process::id()));
+    proto::write_py_stubs(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), proto::py_stubs());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Event {
+    #[prost(string, tag = "1")]
+    pub display_name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub big: i64,
+    #[prost(uint32, tag = "3")]
+    pub small: u32,
+    #[prost(float, tag = "4")]
+    pub ratio: f32,
+    #[prost(double, tag = "5")]
+    pub score: f64,
+    #[prost(bool, tag = "6")]
+    pub flag: bool,
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+    #[prost(enumeration = "Mood", tag = "8")]
+    pub mood: i32,
+    #[prost(enumeration = "Mood", repeated, tag = "9")]
+    pub moods: ::prost::alloc::vec::Vec<i32>,
+    #[prost(int64, repeated, tag = "10")]
+    pub bigs: ::prost::alloc::vec::Vec<i64>,
+    #[prost(map = "string, int64", tag = "11")]
+    pub limits: ::std::collections::HashMap<::prost::alloc::string::String, i64>,
+    #[prost(btree_map = "int32, message", tag = "12")]
+    pub owners: ::prost::alloc::collections::BTreeMap<i32, Owner>,
+    #[prost(message, optional, tag = "13")]
+    pub owner: ::core::option::Option<Owner>,
+    #[prost(message, repeated, tag = "14")]
+    pub owner_list: ::prost::alloc::vec::Vec<Owner>,
+    #[prost(string, optional, tag = "15")]
+    pub note: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(oneof = "event::Pick", tags = "16, 17")]
+    pub pick: ::core::option::Option<event::Pick>,
+}
+/// Nested message and enum types in `Event`.
+pub mod event {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof, pyo3_macro::WithNew)]
+    pub enum Pick {
+        #[prost(int64, tag = "16")]
+        PickedNumber(i64),
+        #[prost(message, tag = "17")]
+        PickedOwner(super::Owner),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+pub struct Owner {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub id: i64,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Mood {
+    Unspecified = 0,
+    Happy = 1,
+    Sad = 2,
+}
+impl Mood {
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Mood::Unspecified => "MOOD_UNSPECIFIED",
+            Mood::Happy => "HAPPY",
+            Mood::Sad => "SAD",
+        }
+    }
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "MOOD_UNSPECIFIED" => Some(Self::Unspecified),
+            "HAPPY" => Some(Self::Happy),
+            "SAD" => Some(Self::Sad),
+            _ => None,
+        }
+    }
+}
+
+// Parses text through the `ProtobufTextParser` of the module, also from its nested inline modules.
+#[pyo3_macro::with_string]
+pub mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    pub struct Note {
+        #[prost(string, tag = "1")]
+        pub text: ::prost::alloc::string::String,
+        #[prost(message, optional, tag = "2")]
+        pub tag: ::core::option::Option<nested::Tag>,
+    }
+
+    pub mod nested {
+        #[pyo3_macro::with_pyclass]
+        #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+        pub struct Tag {
+            #[prost(string, repeated, tag = "1")]
+            pub names: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        }
+    }
+}
+
+fn run(code: &str) {
+    Python::with_gil(|py| {
+        common::run_python(py, code, &[("Event", common::class::<Event>(py)), ("Owner", common::class::<Owner>(py))]);
+    });
+}
+
+#[test]
+fn to_text() {
+    let event = Event {
+        display_name: "h\"é\nllo\\".into(),
+        big: -9007199254740993,
+        small: 7,
+        ratio: 0.5,
+        score: f64::INFINITY,
+        flag: true,
+        data: b"\xfb\x00'".to_vec(),
+        mood: Mood::Happy as i32,
+        // unknown enum values are written as numbers
+        moods: vec![2, 5],
+        bigs: vec![1, 2],
+        limits: [("cpu".to_string(), 4), ("mem".to_string(), 8)].into_iter().collect(),
+        owners: [(-1, Owner { name: "n".into(), id: 1 })].into_iter().collect(),
+        owner: Some(Owner { name: "o".into(), id: 12 }),
+        owner_list: vec![Owner::default()],
+        note: Some(String::new()),
+        pick: Some(event::Pick::PickedOwner(Owner { name: "p".into(), id: 0 })),
+    };
+    let text = event.to_text(false);
+    assert_eq!(
+        text,
+        concat!(
+            "display_name: \"h\\\"\\303\\251\\nllo\\\\\"\n",
+            "big: -9007199254740993\nsmall: 7\nratio: 0.5\nscore: inf\nflag: true\n",
+            "data: \"\\373\\000\\'\"\n",
+            "mood: HAPPY\nmoods: SAD\nmoods: 5\nbigs: 1\nbigs: 2\n",
+            "limits {\n  key: \"cpu\"\n  value: 4\n}\nlimits {\n  key: \"mem\"\n  value: 8\n}\n",
+            "owners {\n  key: -1\n  value {\n    name: \"n\"\n    id: 1\n  }\n}\n",
+            "owner {\n  name: \"o\"\n  id: 12\n}\n",
+            "owner_list {\n}\n",
+            "note: \"\"\n",
+            "picked_owner {\n  name: \"p\"\n}\n",
+        ),
+    );
+    assert!(Event::from_text(&text).unwrap() == event);
+    let line = event.to_text(true);
+    assert!(!line.contains('\n'), "{}", line);
+    assert!(line.starts_with("display_name: \"h\\\"\\303\\251\\nllo\\\\\" big: -9007199254740993"), "{}", line);
+    assert!(Event::from_text(&line).unwrap() == event);
+    run(r#"
+assert Owner("", 0).to_text() == ""
+assert Owner("a", 1).to_text() == 'name: "a"\nid: 1\n'
+assert Owner("a", 1).to_text(as_one_line=True) == 'name: "a" id: 1'
+"#);
+}
+
+#[test]
+fn from_text() {
+    run(r#"
+e = Event.from_text('''
+# a comment
+display_name: 'a' "b"  # adjacent strings
+big: 0x10; small: 010, ratio: 1.5f score: -inf
+mood: SAD moods: [1, HAPPY] moods: 2
+bigs: [] data: "\x41\101é"
+owner < name: "x" id: -3 >
+owner_list { } owner_list: { name: "y" }
+limits { key: "k" value: 1 } limits [{ key: "j", value: 2 }]
+owners { key: 3 value { id: 4 } }
+picked_number: 9
+''')
+assert (e.display_name, e.big, e.small, e.ratio, e.score) == ("ab", 16, 8, 1.5, float("-inf")), e.to_text()
+assert (e.mood, list(e.moods), list(e.bigs)) == (2, [1, 1, 2], []), e.to_text()
+assert bytes(e.data) == "AAé".encode(), e.data
+assert (e.owner.name, e.owner.id, [o.name for o in e.owner_list]) == ("x", -3, ["", "y"])
+assert dict(e.limits) == {"k": 1, "j": 2} and e.owners[3].id == 4
+assert e.picked_number == 9 and e.WhichOneof("pick") == "picked_number"
+assert Event.from_text(e.to_text()).to_text() == e.to_text()
+assert Event.from_text("  # nothing\n").to_text() == ""
+"#);
+}
+
+#[test]
+fn invalid_text() {
+    run(r#"
+for bad in ['unknown: 1', 'mood: ANGRY', 'big: x', 'small: -1', 'display_name "a"', 'owner { name: "a"',
+            'owner { bad: 1 }', 'flag: maybe', 'display_name: "unterminated', 'data: "\\q"']:
+    try:
+        Event.from_text(bad)
+    except ValueError:
+        pass
+    else:
+        raise AssertionError(bad)
+"#);
+}
+
+#[test]
+fn shared_text_parser() {
+    let note = proto::Note { text: "n".into(), tag: Some(proto::nested::Tag { names: vec!["a".into(), "b".into()] }) };
+    assert!(proto::Note::from_text("text: 'n' tag { names: ['a', 'b'] }").unwrap() == note);
+    assert!(proto::nested::Tag::from_text(&note.tag.as_ref().unwrap().to_text(false)).unwrap() == note.tag.unwrap());
+    Python::with_gil(|py| {
+        let err = proto::Note::from_text("tag { names: 1 }").unwrap_err();
+        assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py), "{}", err);
+    });
+}
@@ -0,0 +1,6 @@
+// Compile errors of the macros, the expected messages are in the `.stderr` files next to the cases.
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
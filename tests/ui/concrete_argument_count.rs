@@ -0,0 +1,8 @@
+#[derive(Clone, pyo3_macro::WithNew)]
+#[with_new(concrete(Pair<i64, i64> = "IntPair"))]
+pub struct Pair<T> {
+    pub left: T,
+    pub right: T,
+}
+
+fn main() {}
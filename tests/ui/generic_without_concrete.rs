@@ -0,0 +1,7 @@
+#[derive(Clone, pyo3_macro::WithNew)]
+pub struct Pair<T> {
+    pub left: T,
+    pub right: T,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message, pyo3_macro::WithNew)]
+#[with_new(eq, hash)]
+pub struct Point {
+    #[prost(int64, tag = "1")]
+    pub x: i64,
+}
+
+fn main() {}
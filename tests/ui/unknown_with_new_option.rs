@@ -0,0 +1,9 @@
+#[pyo3_macro::with_pyclass]
+#[derive(Clone, PartialEq, prost::Message, pyo3_macro::WithNew)]
+#[with_new(pretty)]
+pub struct Msg {
+    #[prost(string, tag = "1")]
+    pub text: String,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[pyo3_macro::with_pyclass(name = "Other")]
+#[derive(Clone)]
+pub struct Point {
+    pub x: i64,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[pyo3_macro::with_pyclass]
+pub fn text() -> String {
+    String::new()
+}
+
+fn main() {}
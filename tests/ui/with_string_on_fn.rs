@@ -0,0 +1,6 @@
+#[pyo3_macro::with_string]
+pub fn text() -> String {
+    String::new()
+}
+
+fn main() {}
@@ -0,0 +1,4 @@
+#[pyo3_macro::with_string]
+mod proto;
+
+fn main() {}
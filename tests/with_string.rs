@@ -0,0 +1,70 @@
+mod common;
+
+use pyo3::prelude::*;
+
+#[pyo3_macro::with_string]
+pub mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    #[with_new(decode_error = crate::proto::MessageDecodeError, encode_error = crate::proto::MessageEncodeError)]
+    pub struct Note {
+        #[prost(string, tag = "1")]
+        pub text: ::prost::alloc::string::String,
+    }
+
+    // Refers to the plumbing from inside the module.
+    #[pyo3_macro::with_pyclass]
+    #[derive(Clone, PartialEq, ::prost::Message, pyo3_macro::WithNew)]
+    #[with_new(decode_error = MessageDecodeError, encode_error = MessageEncodeError)]
+    pub struct Tag {
+        #[prost(string, tag = "1")]
+        pub name: ::prost::alloc::string::String,
+    }
+}
+
+#[test]
+fn plumbing_errors() {
+    let err = <proto::Note as prost::Message>::decode(&b"\x0a\x05a"[..]).unwrap_err();
+    assert!(proto::MessageDecodeError::from(err.clone()).to_string().starts_with("failed to parse message: "));
+    Python::with_gil(|py| {
+        let raised: PyErr = proto::MessageDecodeError::from(err).into();
+        assert!(raised.is_instance_of::<pyo3::exceptions::PyOSError>(py));
+    });
+}
+
+#[test]
+fn protobuf_traits() {
+    use proto::{ProtobufDecoder, ProtobufEncoder};
+
+    let note = proto::Note { text: "hi".into() };
+    let bytes = note.encode_proto(note.clone()).unwrap();
+    Python::with_gil(|py| {
+        let bytes = pyo3::types::PyBytes::new_bound(py, &bytes);
+        #[allow(deprecated)]
+        let decoded: proto::Note = note.decode_proto(bytes.into_gil_ref()).unwrap();
+        assert_eq!(decoded, note);
+    });
+}
+
+#[test]
+fn raised_from_python() {
+    Python::with_gil(|py| {
+        common::run_python(
+            py,
+            r#"
+for cls in (Note, Tag):
+    try:
+        cls("").ParseFromString(b"\x0a\x05a")
+        raise AssertionError("expected OSError")
+    except OSError as err:
+        assert str(err).startswith("failed to parse message: "), err
+n = Note("")
+n.ParseFromString(Note("x").SerializeToString())
+assert n.text == "x"
+"#,
+            &[("Note", common::class::<proto::Note>(py)), ("Tag", common::class::<proto::Tag>(py))],
+        );
+    });
+}